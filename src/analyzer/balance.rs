@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap, LinkedList};
+use std::fs::{self, File};
+use std::io::Write;
+
+use crate::engine::tokens::Token;
+use crate::parser::yaml_parser::{ParseError, Result};
+
+/// Units of a component created during a run and held at its end, per actor.
+pub struct Balance {
+    pub component: String,
+    pub created: BTreeMap<String, u64>,
+    pub held: BTreeMap<String, u64>,
+    /// Units scheduled for a delivery that did not happen before the end of the run
+    pub in_flight: u64,
+}
+
+impl Balance {
+    pub fn total_created(&self) -> u64 {
+        self.created.values().sum()
+    }
+
+    pub fn total_held(&self) -> u64 {
+        self.held.values().sum::<u64>() + self.in_flight
+    }
+
+    /// Whether every unit created is still somewhere in the graph.
+    pub fn closes(&self) -> bool {
+        self.total_created() == self.total_held()
+    }
+
+    /// Table of the units created and held by each actor.
+    pub fn breakdown(&self) -> String {
+        let mut res = format!(
+            "{}: {} created, {} held\n",
+            self.component,
+            self.total_created(),
+            self.total_held()
+        );
+        let mut actors: Vec<&String> = self.created.keys().chain(self.held.keys()).collect();
+        actors.sort();
+        actors.dedup();
+        for actor in actors {
+            res.push_str(&format!(
+                "  {}: {} created, {} held\n",
+                actor,
+                self.created.get(actor).unwrap_or(&0),
+                self.held.get(actor).unwrap_or(&0)
+            ));
+        }
+        res.push_str(&format!("  in flight: {}\n", self.in_flight));
+        res
+    }
+}
+
+/// Units of each component in `tokens`, including the parts embedded in composite
+/// tokens.
+pub fn component_units(tokens: &LinkedList<Token>) -> BTreeMap<u16, u64> {
+    let mut units = BTreeMap::new();
+    for (token, count) in tokens.iter().flat_map(|t| t.flatten()) {
+        *units.entry(token.code).or_default() += count;
+    }
+    units
+}
+
+/// Balances of every component, given the units created and held by each actor.
+pub fn compute_balances(
+    created: &BTreeMap<String, Vec<(u16, u64)>>,
+    held: &BTreeMap<String, BTreeMap<u16, u64>>,
+    in_flight: &BTreeMap<u16, u64>,
+    components: &HashMap<u16, String>,
+) -> Vec<Balance> {
+    let mut codes: Vec<&u16> = components.keys().collect();
+    codes.sort();
+    codes
+        .into_iter()
+        .map(|code| Balance {
+            component: components[code].clone(),
+            created: created
+                .iter()
+                .filter_map(|(actor, created)| {
+                    let units: u64 = created
+                        .iter()
+                        .filter(|(c, _)| c == code)
+                        .map(|(_, u)| u)
+                        .sum();
+                    (units > 0).then(|| (actor.clone(), units))
+                })
+                .collect(),
+            held: held
+                .iter()
+                .filter_map(|(actor, held)| held.get(code).map(|u| (actor.clone(), *u)))
+                .collect(),
+            in_flight: in_flight.get(code).copied().unwrap_or(0),
+        })
+        .collect()
+}
+
+/// Writes the balances in `balance.csv`, the in-flight units being written as held by
+/// an `in_flight` actor.
+pub fn write_balances(balances: &[Balance], folder: &str) {
+    fs::create_dir_all(folder).unwrap();
+    let mut file = File::create(format!("{}/balance.csv", folder)).unwrap();
+    writeln!(file, "component,actor,created,held").unwrap();
+    for balance in balances.iter() {
+        let mut actors: Vec<&String> = balance.created.keys().chain(balance.held.keys()).collect();
+        actors.sort();
+        actors.dedup();
+        for actor in actors {
+            writeln!(
+                file,
+                "{},{},{},{}",
+                balance.component,
+                actor,
+                balance.created.get(actor).unwrap_or(&0),
+                balance.held.get(actor).unwrap_or(&0)
+            )
+            .unwrap();
+        }
+        writeln!(
+            file,
+            "{},in_flight,0,{}",
+            balance.component, balance.in_flight
+        )
+        .unwrap();
+    }
+}
+
+/// Fails with the breakdown of the components whose balance does not close, as units
+/// were then created or lost by the engine itself.
+pub fn check_balances(balances: &[Balance]) -> Result<()> {
+    let failures: Vec<String> = balances
+        .iter()
+        .filter(|balance| !balance.closes())
+        .map(|balance| balance.breakdown())
+        .collect();
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(ParseError::Balance(failures)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances(held_by_sink: u64) -> Vec<Balance> {
+        let created = BTreeMap::from([(String::from("production"), vec![(1, 100)])]);
+        let held = BTreeMap::from([
+            (String::from("use"), BTreeMap::from([(1, 30)])),
+            (
+                String::from("landfill"),
+                BTreeMap::from([(1, held_by_sink)]),
+            ),
+        ]);
+        let in_flight = BTreeMap::from([(1, 10)]);
+        let components = HashMap::from([(1, String::from("plastic"))]);
+        compute_balances(&created, &held, &in_flight, &components)
+    }
+
+    #[test]
+    fn closing_balance() {
+        let balances = balances(60);
+        assert_eq!(balances[0].total_created(), 100);
+        assert_eq!(balances[0].total_held(), 100);
+        assert!(check_balances(&balances).is_ok());
+    }
+
+    #[test]
+    fn balance_not_closing() {
+        let Err(ParseError::Balance(failures)) = check_balances(&balances(59)) else {
+            panic!("the balance should not close");
+        };
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].starts_with("plastic: 100 created, 99 held\n"));
+        assert!(failures[0].contains("  landfill: 0 created, 59 held\n"));
+        assert!(failures[0].contains("  in flight: 10\n"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+
+/// Units sent along the route of a component from an actor to one of its clients.
+pub struct Flow {
+    pub from: String,
+    pub to: String,
+    pub component: String,
+    pub quantity: u64,
+    /// Units sent per timestep
+    pub timeline: BTreeMap<usize, u64>,
+}
+
+/// Labels the flows recorded by the scheduler, given the labels of the actor and
+/// component codes.
+pub fn label_flows(
+    flows: &BTreeMap<(u16, u16, u16), BTreeMap<usize, u64>>,
+    actors: &HashMap<u16, String>,
+    components: &HashMap<u16, String>,
+) -> Vec<Flow> {
+    flows
+        .iter()
+        .map(|((from, to, component), timeline)| Flow {
+            from: actors[from].clone(),
+            to: actors[to].clone(),
+            component: components[component].clone(),
+            quantity: timeline.values().sum(),
+            timeline: timeline.clone(),
+        })
+        .collect()
+}
+
+/// Writes the flows as an edge list in `flows.csv` and `flows_bins.csv`, and as a
+/// Sankey diagram in `sankey.json`. Bins last `bin` timesteps and their bounds are
+/// given in units of time.
+pub fn write_flows(flows: &[Flow], bin: usize, dt: f64, folder: &str) {
+    fs::create_dir_all(folder).unwrap();
+    let mut file = File::create(format!("{}/flows.csv", folder)).unwrap();
+    writeln!(file, "from,to,component,quantity").unwrap();
+    for flow in flows.iter() {
+        writeln!(
+            file,
+            "{},{},{},{}",
+            flow.from, flow.to, flow.component, flow.quantity
+        )
+        .unwrap();
+    }
+    let bins = binned(flows, bin);
+    let mut file = File::create(format!("{}/flows_bins.csv", folder)).unwrap();
+    writeln!(file, "start,end,from,to,component,quantity").unwrap();
+    for (index, quantities) in bins.iter() {
+        for (flow, quantity) in flows.iter().zip(quantities) {
+            if *quantity == 0 {
+                continue;
+            }
+            writeln!(
+                file,
+                "{},{},{},{},{},{}",
+                (index * bin) as f64 * dt,
+                ((index + 1) * bin) as f64 * dt,
+                flow.from,
+                flow.to,
+                flow.component,
+                quantity
+            )
+            .unwrap();
+        }
+    }
+    let mut nodes: Vec<&str> = vec![];
+    for flow in flows.iter() {
+        for label in [&flow.from, &flow.to] {
+            if !nodes.contains(&label.as_str()) {
+                nodes.push(label);
+            }
+        }
+    }
+    let links = |quantities: &mut dyn Iterator<Item = (&Flow, u64)>| -> String {
+        let links: Vec<String> = quantities
+            .filter(|(_, quantity)| *quantity > 0)
+            .map(|(flow, quantity)| {
+                format!(
+                    "{{\"source\": {}, \"target\": {}, \"component\": \"{}\", \"value\": {}}}",
+                    nodes.iter().position(|n| *n == flow.from).unwrap(),
+                    nodes.iter().position(|n| *n == flow.to).unwrap(),
+                    escape(&flow.component),
+                    quantity
+                )
+            })
+            .collect();
+        format!("[{}]", links.join(", "))
+    };
+    let names: Vec<String> = nodes
+        .iter()
+        .map(|n| format!("{{\"name\": \"{}\"}}", escape(n)))
+        .collect();
+    let bins: Vec<String> = bins
+        .iter()
+        .map(|(index, quantities)| {
+            format!(
+                "{{\"start\": {}, \"end\": {}, \"links\": {}}}",
+                (index * bin) as f64 * dt,
+                ((index + 1) * bin) as f64 * dt,
+                links(&mut flows.iter().zip(quantities.iter().copied()))
+            )
+        })
+        .collect();
+    let mut file = File::create(format!("{}/sankey.json", folder)).unwrap();
+    writeln!(
+        file,
+        "{{\"nodes\": [{}],\n \"links\": {},\n \"bins\": [\n  {}\n ]}}",
+        names.join(", "),
+        links(&mut flows.iter().map(|flow| (flow, flow.quantity))),
+        bins.join(",\n  ")
+    )
+    .unwrap();
+}
+
+/// Quantities of each flow per bin of `bin` timesteps, for the bins with any flow.
+fn binned(flows: &[Flow], bin: usize) -> BTreeMap<usize, Vec<u64>> {
+    let mut bins: BTreeMap<usize, Vec<u64>> = BTreeMap::new();
+    for (index, flow) in flows.iter().enumerate() {
+        for (time, quantity) in flow.timeline.iter() {
+            bins.entry(time / bin)
+                .or_insert_with(|| vec![0; flows.len()])[index] += quantity;
+        }
+    }
+    bins
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
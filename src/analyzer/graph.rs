@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use crate::engine::time_series::TimeSeries;
+use crate::parser::validation::{Edge, Graph, Node};
+use crate::parser::yaml_parser::{Config, ParseError, Result};
+
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl GraphFormat {
+    pub fn parse(format: &str) -> Result<GraphFormat> {
+        match format {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            format => Err(ParseError::WrongFormat(format!(
+                "Unknown graph format {}, expected dot or mermaid",
+                format
+            ))),
+        }
+    }
+}
+
+/// Writes the network of actors of `config` as a Graphviz or Mermaid diagram.
+pub fn export_graph(config: &Config, format: GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => to_dot(&config.graph),
+        GraphFormat::Mermaid => to_mermaid(&config.graph),
+    }
+}
+
+/// Label of a route, with the share of the product it takes at the start of the
+/// simulation among the routes of the same product. The loss rate of a compartment is
+/// already a share of all the units, and routes with a condition take the matching
+/// units whatever their weight.
+fn edge_label(edge: &Edge, totals: &HashMap<(&str, &str), f64>) -> String {
+    let total = match edge.product.as_str() {
+        "loss" => 1.0,
+        product => totals[&(edge.from.as_str(), product)],
+    };
+    let share = match total > 0.0 && !edge.conditional {
+        true => format!(" {:.0}%", 100.0 * edge.share.at(0) / total),
+        false => String::new(),
+    };
+    let varying = match edge.share {
+        TimeSeries::Constant(_) => "",
+        _ => " (varying)",
+    };
+    let condition = match edge.conditional {
+        true => " when",
+        false => "",
+    };
+    format!("{}{}{}{}", edge.product, share, varying, condition)
+}
+
+/// Sum of the weights of the routes without condition, per actor and product.
+fn total_weights(graph: &Graph) -> HashMap<(&str, &str), f64> {
+    let mut totals = HashMap::new();
+    for edge in graph.edges.iter() {
+        let total = totals
+            .entry((edge.from.as_str(), edge.product.as_str()))
+            .or_insert(0.0);
+        if !edge.conditional {
+            *total += edge.share.at(0).max(0.0);
+        }
+    }
+    totals
+}
+
+/// Lines of the label of a node, its logged components following its name.
+fn node_lines(node: &Node) -> Vec<String> {
+    let mut lines = vec![node.label.clone()];
+    for (product, description) in node.logs.iter() {
+        lines.push(match description {
+            Some(description) => format!("log {}: {}", product, description),
+            None => format!("log {}", product),
+        });
+    }
+    lines
+}
+
+fn to_dot(graph: &Graph) -> String {
+    let mut res = String::from("digraph componentflow {\n    rankdir=LR;\n");
+    for node in graph.nodes.iter() {
+        let style = match node.actor_type.as_str() {
+            "SimpleSource" | "TimeSeriesSource" => "shape=invhouse, fillcolor=\"#c8e6c9\"",
+            "SimpleSink" => "shape=cylinder, fillcolor=\"#e0e0e0\"",
+            "SimpleActor" => "shape=box, fillcolor=\"#bbdefb\"",
+            _ => "shape=hexagon, fillcolor=\"#ffe0b2\"",
+        };
+        let border = match node.logs.is_empty() {
+            true => "",
+            false => ", penwidth=2",
+        };
+        let lines: Vec<String> = node_lines(node).iter().map(|l| escape(l)).collect();
+        res.push_str(&format!(
+            "    \"{}\" [label=\"{}\", style=filled, {}{}];\n",
+            escape(&node.label),
+            lines.join("\\n"),
+            style,
+            border
+        ));
+    }
+    let totals = total_weights(graph);
+    for edge in graph.edges.iter() {
+        let style = match edge.conditional {
+            true => ", style=dashed",
+            false => "",
+        };
+        res.push_str(&format!(
+            "    \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+            escape(&edge.from),
+            escape(&edge.to),
+            escape(&edge_label(edge, &totals)),
+            style
+        ));
+    }
+    res.push_str("}\n");
+    res
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn to_mermaid(graph: &Graph) -> String {
+    let mut res = String::from("flowchart LR\n");
+    // Identifiers only keep the characters Mermaid accepts, labels being quoted
+    let id = |label: &str| -> String {
+        let id: String = label
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("actor_{}", id)
+    };
+    for node in graph.nodes.iter() {
+        let lines: Vec<String> = node_lines(node)
+            .iter()
+            .map(|l| l.replace('"', "#quot;"))
+            .collect();
+        let text = lines.join("<br/>");
+        let (shape, class) = match node.actor_type.as_str() {
+            "SimpleSource" | "TimeSeriesSource" => (format!("([\"{}\"])", text), "source"),
+            "SimpleSink" => (format!("[(\"{}\")]", text), "sink"),
+            "SimpleActor" => (format!("[\"{}\"]", text), "actor"),
+            _ => (format!("{{{{\"{}\"}}}}", text), "other"),
+        };
+        res.push_str(&format!("    {}{}:::{}\n", id(&node.label), shape, class));
+        if !node.logs.is_empty() {
+            res.push_str(&format!("    class {} logged\n", id(&node.label)));
+        }
+    }
+    let totals = total_weights(graph);
+    for edge in graph.edges.iter() {
+        let arrow = match edge.conditional {
+            true => "-.->",
+            false => "-->",
+        };
+        res.push_str(&format!(
+            "    {} {}|\"{}\"| {}\n",
+            id(&edge.from),
+            arrow,
+            edge_label(edge, &totals).replace('"', "#quot;"),
+            id(&edge.to)
+        ));
+    }
+    res.push_str("    classDef source fill:#c8e6c9\n");
+    res.push_str("    classDef sink fill:#e0e0e0\n");
+    res.push_str("    classDef actor fill:#bbdefb\n");
+    res.push_str("    classDef other fill:#ffe0b2\n");
+    res.push_str("    classDef logged stroke-width:3px\n");
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::yaml_parser::tests::{config, simulate};
+
+    use super::*;
+
+    /// Sorting of logged plastic into reuse, a conditional landfill and an air
+    /// compartment.
+    const SORTING: &str = "
+global: {time_window: 1, dt: 1.0}
+components: [plastic]
+actors:
+  production:
+    type: SimpleSource
+    source: true
+    component: plastic
+    speed: {time: 1, quantity: 10}
+    clients: {sorting: {plastic: 1}}
+  sorting:
+    type: LossActor
+    component: plastic
+    log: {plastic: null}
+    compartments: {air: 0.1}
+    clients:
+      reuse: {plastic: 1}
+      landfill: {plastic: 3, when: quality < 0.5}
+  reuse: {type: SimpleSink, component: plastic}
+  landfill: {type: SimpleSink, component: plastic}
+  air: {type: SimpleSink, component: plastic}
+";
+
+    #[test]
+    fn dot_styles_nodes_and_routes() {
+        let dot = export_graph(&config(SORTING).unwrap(), GraphFormat::Dot);
+        for line in [
+            "digraph componentflow {",
+            "    \"production\" [label=\"production\", style=filled, shape=invhouse, fillcolor=\"#c8e6c9\"];",
+            "    \"sorting\" [label=\"sorting\\nlog plastic\", style=filled, shape=hexagon, fillcolor=\"#ffe0b2\", penwidth=2];",
+            "    \"sorting\" -> \"air\" [label=\"loss 10%\"];",
+            "    \"sorting\" -> \"reuse\" [label=\"plastic 100%\"];",
+            "    \"sorting\" -> \"landfill\" [label=\"plastic when\", style=dashed];",
+        ] {
+            assert!(dot.lines().any(|l| l == line), "{}\n{}", line, dot);
+        }
+    }
+
+    #[test]
+    fn mermaid_styles_nodes_and_routes() {
+        let mermaid = export_graph(&config(SORTING).unwrap(), GraphFormat::Mermaid);
+        for line in [
+            "flowchart LR",
+            "    actor_production([\"production\"]):::source",
+            "    actor_reuse[(\"reuse\")]:::sink",
+            "    class actor_sorting logged",
+            "    actor_sorting -->|\"plastic 100%\"| actor_reuse",
+            "    actor_sorting -.->|\"plastic when\"| actor_landfill",
+        ] {
+            assert!(mermaid.lines().any(|l| l == line), "{}\n{}", line, mermaid);
+        }
+    }
+
+    #[test]
+    fn shares_match_the_simulation_with_a_conditional_route() {
+        let config = config(
+            "
+global: {time_window: 1, dt: 1.0, seed: 1}
+components: [plastic]
+actors:
+  production:
+    type: SimpleSource
+    source: true
+    component: plastic
+    speed: {time: 1, quantity: 1000}
+    max_production: 1000
+    clients:
+      sorting: {plastic: 1}
+  sorting:
+    type: SimpleActor
+    component: plastic
+    clients:
+      reuse: {plastic: 1}
+      recycling: {plastic: 3}
+      landfill: {plastic: 4, when: quality < 0.5}
+  reuse: {type: SimpleSink, component: plastic}
+  recycling: {type: SimpleSink, component: plastic}
+  landfill: {type: SimpleSink, component: plastic}
+",
+        )
+        .unwrap();
+        simulate(&config).unwrap();
+        let totals = total_weights(&config.graph);
+        let flows = &config.scheduler.lock().unwrap().flows;
+        let code = |label: &str| config.actors[label].lock().unwrap().code();
+        let routed = |to: &str| -> u64 {
+            flows
+                .iter()
+                .filter(|((from, client, _), _)| *from == code("sorting") && *client == code(to))
+                .flat_map(|(_, timeline)| timeline.values())
+                .sum()
+        };
+        for edge in config.graph.edges.iter().filter(|e| e.from == "sorting") {
+            let share = match edge.conditional {
+                true => 0.0,
+                false => edge.share.at(0) / totals[&("sorting", "plastic")],
+            };
+            assert_eq!(routed(&edge.to) as f64, 1000.0 * share, "{}", edge.to);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use crate::parser::validation::Graph;
+
+/// Absorbing Markov chain followed by the units of a component. Transient states are
+/// the actors sending the component to clients, the others absorbing it, such as sinks.
+/// Transitions are the shares of the routes at the start of the simulation, routes with
+/// a condition as well as `residue` and `overflow` routes being ignored.
+pub struct MarkovChain {
+    pub component: String,
+    pub transient: Vec<String>,
+    pub absorbing: Vec<String>,
+    /// Transition probabilities between transient states
+    pub q: Vec<Vec<f64>>,
+    /// Transition probabilities from transient to absorbing states
+    pub r: Vec<Vec<f64>>,
+}
+
+/// Expected outcome of the units entering the chain.
+pub struct MarkovSolution {
+    /// Expected entries in each transient actor
+    pub visits: Vec<f64>,
+    /// Expected units ending in each absorbing actor
+    pub absorbed: Vec<f64>,
+    /// Expected entries in each transient actor of a unit that entered it once
+    pub cycles: Vec<f64>,
+}
+
+/// Chains of the components routed in `graph`, in the order of `components`. As routes
+/// with a condition, `overflow` and `residue` routes are ignored, the units they carry
+/// in the simulation, such as those turned away by a full capacity, are not reproduced.
+pub fn markov_chains(graph: &Graph, components: &[String]) -> Vec<MarkovChain> {
+    components
+        .iter()
+        .filter_map(|component| MarkovChain::new(graph, component))
+        .collect()
+}
+
+impl MarkovChain {
+    fn new(graph: &Graph, component: &str) -> Option<MarkovChain> {
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.product == component && !edge.conditional)
+            .collect();
+        if edges.is_empty() {
+            return None;
+        }
+        let mut weights: HashMap<&str, f64> = HashMap::new();
+        for edge in edges.iter() {
+            *weights.entry(&edge.from).or_default() += edge.share.at(0).max(0.0);
+        }
+        let (mut transient, mut absorbing) = (vec![], vec![]);
+        // Actors are kept in the order of the configuration
+        for node in graph.nodes.iter() {
+            let label = node.label.as_str();
+            if weights.get(label).is_some_and(|w| *w > 0.0) {
+                transient.push(String::from(label));
+            } else if edges.iter().any(|edge| edge.to == label) {
+                absorbing.push(String::from(label));
+            }
+        }
+        let mut q = vec![vec![0.0; transient.len()]; transient.len()];
+        let mut r = vec![vec![0.0; absorbing.len()]; transient.len()];
+        for edge in edges.iter() {
+            let Some(i) = transient.iter().position(|t| *t == edge.from) else {
+                continue;
+            };
+            let p = edge.share.at(0).max(0.0) / weights[edge.from.as_str()];
+            match transient.iter().position(|t| *t == edge.to) {
+                Some(j) => q[i][j] += p,
+                None => {
+                    let k = absorbing.iter().position(|a| *a == edge.to).unwrap();
+                    r[i][k] += p;
+                }
+            }
+        }
+        Some(MarkovChain {
+            component: String::from(component),
+            transient,
+            absorbing,
+            q,
+            r,
+        })
+    }
+
+    /// Fundamental matrix `(I - Q)^-1`, whose `(i, j)` entry is the expected number of
+    /// entries in `j` of a unit starting in `i`. None if some units are never absorbed.
+    pub fn fundamental(&self) -> Option<Vec<Vec<f64>>> {
+        let n = self.transient.len();
+        let mut a: Vec<Vec<f64>> = (0..n)
+            .map(|i| {
+                (0..n)
+                    .map(|j| (i == j) as u8 as f64 - self.q[i][j])
+                    .collect()
+            })
+            .collect();
+        let mut inverse: Vec<Vec<f64>> = (0..n)
+            .map(|i| (0..n).map(|j| (i == j) as u8 as f64).collect())
+            .collect();
+        // Gauss-Jordan elimination with partial pivoting
+        for col in 0..n {
+            let pivot = (col..n).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inverse.swap(col, pivot);
+            let p = a[col][col];
+            for j in 0..n {
+                a[col][j] /= p;
+                inverse[col][j] /= p;
+            }
+            for row in 0..n {
+                if row == col || a[row][col] == 0.0 {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..n {
+                    a[row][j] -= factor * a[col][j];
+                    inverse[row][j] -= factor * inverse[col][j];
+                }
+            }
+        }
+        Some(inverse)
+    }
+
+    /// Expected outcome of `inflow` units entering the transient actors, given by label.
+    pub fn solve(&self, inflow: &HashMap<String, f64>) -> Option<MarkovSolution> {
+        let fundamental = self.fundamental()?;
+        let n = self.transient.len();
+        let entering: Vec<f64> = self
+            .transient
+            .iter()
+            .map(|label| inflow.get(label).copied().unwrap_or(0.0))
+            .collect();
+        let visits: Vec<f64> = (0..n)
+            .map(|j| (0..n).map(|i| entering[i] * fundamental[i][j]).sum())
+            .collect();
+        let absorbed = (0..self.absorbing.len())
+            .map(|k| (0..n).map(|i| visits[i] * self.r[i][k]).sum())
+            .collect();
+        Some(MarkovSolution {
+            visits,
+            absorbed,
+            cycles: (0..n).map(|j| fundamental[j][j]).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(q: Vec<Vec<f64>>, r: Vec<Vec<f64>>) -> MarkovChain {
+        MarkovChain {
+            component: String::from("plastic"),
+            transient: vec![String::from("use"), String::from("recycling")],
+            absorbing: vec![String::from("discard")],
+            q,
+            r,
+        }
+    }
+
+    #[test]
+    fn fundamental_matrix_of_a_two_state_chain() {
+        let chain = chain(
+            vec![vec![0.2, 0.3], vec![0.5, 0.0]],
+            vec![vec![0.5], vec![0.5]],
+        );
+        // (I - Q)^-1 = [[1, 0.3], [0.5, 0.8]] / 0.65
+        let expected = [[1.0, 0.3], [0.5, 0.8]];
+        let fundamental = chain.fundamental().unwrap();
+        for (row, expected) in fundamental.iter().zip(expected) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!((value - expected / 0.65).abs() < 1e-9);
+            }
+        }
+        let solution = chain
+            .solve(&HashMap::from([(String::from("use"), 100.0)]))
+            .unwrap();
+        assert!((solution.visits[0] - 100.0 / 0.65).abs() < 1e-9);
+        assert!((solution.visits[1] - 30.0 / 0.65).abs() < 1e-9);
+        assert!((solution.absorbed[0] - 100.0).abs() < 1e-9);
+        assert!((solution.cycles[1] - 0.8 / 0.65).abs() < 1e-9);
+    }
+
+    #[test]
+    fn chain_never_absorbed() {
+        let chain = chain(
+            vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            vec![vec![0.0], vec![0.0]],
+        );
+        assert!(chain.fundamental().is_none());
+        assert!(chain.solve(&HashMap::new()).is_none());
+    }
+}
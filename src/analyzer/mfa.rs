@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+
+use crate::engine::actor::MAX_RESIDENCE_DRAWS;
+use crate::parser::validation::Graph;
+
+use super::Cdf;
+
+/// Probability left out of the tail of a distribution.
+const PDF_TOLERANCE: f64 = 1e-12;
+
+/// Maximum number of timesteps of a distribution, for those never reaching the tolerance.
+const MAX_PDF_LENGTH: usize = 1_000_000;
+
+/// Maximum number of passes solving the routes without delay within a timestep.
+const MAX_PASSES: usize = 1000;
+
+/// Expected units of a component entering, leaving and held by an actor, per timestep.
+pub struct MfaCurves {
+    pub actor: String,
+    pub component: String,
+    pub inflow: Vec<f64>,
+    pub outflow: Vec<f64>,
+    pub stock: Vec<f64>,
+}
+
+/// Probability of each number of timesteps, as differences of the cumulative
+/// distribution `cdf`.
+pub fn residence_pdf(cdf: &Cdf) -> Vec<f64> {
+    let mut pdf = vec![];
+    let mut below = 0.0;
+    while below < 1.0 - PDF_TOLERANCE && pdf.len() < MAX_PDF_LENGTH {
+        let next = cdf(pdf.len());
+        pdf.push(next - below);
+        below = next;
+    }
+    pdf
+}
+
+/// Probability of each release delay of units of the initial stock, whose `age` and
+/// `residence` time are given by their probability per timestep. As drawn by
+/// [SimpleActor][crate::engine::actor::SimpleActor], the residence time is drawn again
+/// until longer than the age, the units leaving at once when no draw is.
+pub fn residual_pdf(age: &[f64], residence: &[f64]) -> Vec<f64> {
+    // Probability of a residence time longer than each number of timesteps
+    let mut longer = vec![0.0; residence.len()];
+    for l in (1..residence.len()).rev() {
+        longer[l - 1] = longer[l] + residence[l];
+    }
+    let mut pdf = vec![0.0; residence.len().max(1)];
+    for (a, p_age) in age.iter().enumerate() {
+        let survival = longer.get(a).copied().unwrap_or(0.0);
+        let found = 1.0 - (1.0 - survival).powi(MAX_RESIDENCE_DRAWS as i32);
+        pdf[0] += p_age * (1.0 - found);
+        if survival <= 0.0 {
+            continue;
+        }
+        for l in a + 1..residence.len() {
+            pdf[l - a] += p_age * found * residence[l] / survival;
+        }
+    }
+    pdf
+}
+
+/// Convolution-based dynamic MFA of `component` over `max_time` timesteps. Units are
+/// `supplied` to actors, given by label, then each actor releases them following its
+/// residence time distribution, units without one leaving at once, and splits them
+/// between its clients by the weights of the routes of the component. Actors without
+/// such routes keep the units. The `initial` stock of an actor, given with the
+/// probability of each of its release delays, enters it at the first timestep.
+/// Routes with a condition are ignored, as well as the `overflow` and `residue` routes
+/// and the capacities of the actors, so flows depending on them are not reproduced.
+pub fn dynamic_mfa(
+    graph: &Graph,
+    component: &str,
+    residence: &HashMap<String, Vec<f64>>,
+    supply: &HashMap<String, Vec<f64>>,
+    initial: &HashMap<String, (f64, Vec<f64>)>,
+    max_time: usize,
+) -> Vec<MfaCurves> {
+    let edges: Vec<_> = graph
+        .edges
+        .iter()
+        .filter(|edge| edge.product == component && !edge.conditional)
+        .collect();
+    let labels: Vec<&str> = graph.nodes.iter().map(|node| node.label.as_str()).collect();
+    let index = |label: &str| labels.iter().position(|l| *l == label).unwrap();
+    let routes: Vec<(usize, usize, &_)> = edges
+        .iter()
+        .map(|edge| (index(&edge.from), index(&edge.to), &edge.share))
+        .collect();
+    let senders: Vec<bool> = (0..labels.len())
+        .map(|i| routes.iter().any(|(from, _, _)| *from == i))
+        .collect();
+    let instant = vec![1.0];
+    let pdfs: Vec<&Vec<f64>> = labels
+        .iter()
+        .map(|label| residence.get(*label).unwrap_or(&instant))
+        .collect();
+    let mut inflow = vec![vec![0.0; max_time]; labels.len()];
+    let mut outflow = vec![vec![0.0; max_time]; labels.len()];
+    for t in 0..max_time {
+        // Shares of the routes at this timestep among the routes of their actor
+        let mut totals = vec![0.0; labels.len()];
+        for (from, _, share) in routes.iter() {
+            totals[*from] += share.at(t).max(0.0);
+        }
+        let shares: Vec<f64> = routes
+            .iter()
+            .map(|(from, _, share)| match totals[*from] > 0.0 {
+                true => share.at(t).max(0.0) / totals[*from],
+                false => 0.0,
+            })
+            .collect();
+        // Units released now after entering at an earlier timestep or being in stock
+        let released: Vec<f64> = labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| match senders[i] {
+                true => {
+                    let stock = initial.get(*label).map_or(0.0, |(units, pdf)| {
+                        units * pdf.get(t).copied().unwrap_or(0.0)
+                    });
+                    let entered: f64 = (1..pdfs[i].len().min(t + 1))
+                        .map(|delay| inflow[i][t - delay] * pdfs[i][delay])
+                        .sum();
+                    stock + entered
+                }
+                false => 0.0,
+            })
+            .collect();
+        let supplied: Vec<f64> = labels
+            .iter()
+            .map(|label| {
+                supply
+                    .get(*label)
+                    .and_then(|s| s.get(t))
+                    .copied()
+                    .unwrap_or(0.0)
+            })
+            .collect();
+        // Units leaving without delay enter their clients within the same timestep
+        let mut entering = supplied.clone();
+        for _ in 0..MAX_PASSES {
+            let mut next = supplied.clone();
+            for ((from, to, _), share) in routes.iter().zip(shares.iter()) {
+                next[*to] += share * (released[*from] + pdfs[*from][0] * entering[*from]);
+            }
+            let change = next
+                .iter()
+                .zip(entering.iter())
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f64::max);
+            entering = next;
+            if change < 1e-9 {
+                break;
+            }
+        }
+        for i in 0..labels.len() {
+            inflow[i][t] = entering[i];
+            if senders[i] {
+                outflow[i][t] = released[i] + pdfs[i][0] * entering[i];
+            }
+        }
+    }
+    // The initial stock is counted as entering at the first timestep
+    for (i, label) in labels.iter().enumerate() {
+        if let (Some((units, _)), Some(first)) = (initial.get(*label), inflow[i].first_mut()) {
+            *first += units;
+        }
+    }
+    labels
+        .iter()
+        .zip(inflow.into_iter().zip(outflow))
+        .filter(|(_, (inflow, _))| inflow.iter().any(|units| *units > 0.0))
+        .map(|(label, (inflow, outflow))| {
+            let mut stock = Vec::with_capacity(max_time);
+            let mut held = 0.0;
+            for (entered, left) in inflow.iter().zip(outflow.iter()) {
+                held += entered - left;
+                stock.push(held);
+            }
+            MfaCurves {
+                actor: String::from(*label),
+                component: String::from(component),
+                inflow,
+                outflow,
+                stock,
+            }
+        })
+        .collect()
+}
+
+/// Writes the curves in `mfa.csv`, in the folder of the logs of the actor and
+/// component next to the curves of the tokens.
+pub fn write_mfa(curves: &MfaCurves, logs_folder: &str) {
+    let folder = format!("{}/{}/{}", logs_folder, curves.actor, curves.component);
+    fs::create_dir_all(&folder).unwrap();
+    let mut file = File::create(format!("{}/mfa.csv", folder)).unwrap();
+    writeln!(file, "time,inflow,outflow,stock").unwrap();
+    for time in 0..curves.inflow.len() {
+        writeln!(
+            file,
+            "{},{},{},{}",
+            time, curves.inflow[time], curves.outflow[time], curves.stock[time]
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn residence_pdf_takes_cdf_differences() {
+        let cdf: Cdf = Arc::new(|timesteps: usize| (timesteps as f64 + 1.0).min(4.0) / 4.0);
+        assert_eq!(residence_pdf(&cdf), vec![0.25; 4]);
+    }
+
+    #[test]
+    fn residual_pdf_conditions_on_age() {
+        // Residence of 5 timesteps, units aged 2 or 6 timesteps
+        let residence = [0.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let age = [0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.5];
+        let pdf = residual_pdf(&age, &residence);
+        assert_eq!(pdf[0], 0.5);
+        assert_eq!(pdf[3], 0.5);
+        assert_eq!(pdf.iter().sum::<f64>(), 1.0);
+    }
+}
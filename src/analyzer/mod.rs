@@ -1,4 +1,16 @@
+pub mod balance;
+pub mod flows;
+pub mod graph;
+pub mod markov;
+pub mod mfa;
 pub mod plot;
+pub mod sensitivity;
 pub mod timeline;
 
-pub type Sampler = Box<dyn Fn() -> usize + Sync>;
+use rand_chacha::ChaCha8Rng;
+use std::sync::Arc;
+
+pub type Sampler = Arc<dyn Fn(&mut ChaCha8Rng) -> usize + Send + Sync>;
+
+/// Probability that a [Sampler] draws at most the given number of timesteps.
+pub type Cdf = Arc<dyn Fn(usize) -> f64 + Send + Sync>;
@@ -0,0 +1,244 @@
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{BTreeMap, HashMap};
+
+use super::timeline::Timeline;
+use crate::parser::yaml_parser::ActorLogInfos;
+
+/// Uncertain parameter of the configuration, uniformly distributed between its bounds.
+pub struct Parameter {
+    /// Dot separated path of the parameter in the configuration
+    pub path: String,
+    pub min: f64,
+    pub max: f64,
+    /// Samples are rounded, for parameters only accepting integers
+    pub integer: bool,
+}
+
+impl Parameter {
+    /// Value of the parameter at the relative position `u` between its bounds.
+    pub fn value(&self, u: f64) -> f64 {
+        let value = self.min + u * (self.max - self.min);
+        match self.integer {
+            true => value.round(),
+            false => value,
+        }
+    }
+}
+
+/// Scalar result of a run the sensitivity is computed for.
+pub enum Output {
+    /// Total of an actor at the end of the simulation
+    Total(String),
+    /// Maximum over time of the units of a logged `actor/product` in its actor
+    PeakStock(String),
+    /// Units of a logged `actor/product` in its actor at the end of the simulation
+    FinalStock(String),
+}
+
+impl Output {
+    /// Value of the output for a run, None if it refers to an unknown actor or product.
+    pub fn evaluate(
+        &self,
+        totals: &BTreeMap<String, u64>,
+        timeline: &Timeline,
+        logs: &HashMap<u16, ActorLogInfos>,
+    ) -> Option<f64> {
+        let stocks = |product_code: &str| {
+            logs.values()
+                .find(|infos| infos.product_code == product_code)
+                .map(|infos| timeline.occupencies.row(infos.index))
+        };
+        match self {
+            Output::Total(label) => totals.get(label).map(|total| *total as f64),
+            Output::PeakStock(product_code) => {
+                stocks(product_code).map(|s| s.iter().copied().max().unwrap_or(0) as f64)
+            }
+            Output::FinalStock(product_code) => {
+                stocks(product_code).map(|s| s.last().copied().unwrap_or(0) as f64)
+            }
+        }
+    }
+}
+
+pub enum Method {
+    /// Elementary effects along `levels` grid trajectories
+    Morris { levels: usize },
+    /// First and total order indices from a Saltelli design
+    Sobol,
+}
+
+pub struct Sensitivity {
+    pub method: Method,
+    /// Number of trajectories for Morris, of base samples for Sobol
+    pub samples: usize,
+    pub parameters: Vec<Parameter>,
+    pub outputs: Vec<(String, Output)>,
+}
+
+impl Sensitivity {
+    /// Points to run, as relative positions of the parameters between their bounds.
+    /// Morris gives `samples * (k + 1)` points and Sobol `samples * (k + 2)` points.
+    pub fn design(&self, seed: u64) -> Vec<Vec<f64>> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let k = self.parameters.len();
+        let mut design = vec![];
+        match self.method {
+            Method::Morris { levels } => {
+                let delta = morris_delta(levels);
+                for _ in 0..self.samples {
+                    // Base point low enough for every parameter to be increased by delta
+                    let mut point: Vec<f64> = (0..k)
+                        .map(|_| rng.gen_range(0..levels / 2) as f64 / (levels - 1) as f64)
+                        .collect();
+                    let mut order: Vec<usize> = (0..k).collect();
+                    order.shuffle(&mut rng);
+                    design.push(point.clone());
+                    for i in order {
+                        point[i] += delta;
+                        design.push(point.clone());
+                    }
+                }
+            }
+            Method::Sobol => {
+                for _ in 0..self.samples {
+                    let a: Vec<f64> = (0..k).map(|_| rng.gen()).collect();
+                    let b: Vec<f64> = (0..k).map(|_| rng.gen()).collect();
+                    design.push(a.clone());
+                    design.push(b.clone());
+                    for i in 0..k {
+                        let mut ab = a.clone();
+                        ab[i] = b[i];
+                        design.push(ab);
+                    }
+                }
+            }
+        }
+        design
+    }
+
+    pub fn index_names(&self) -> &[&str] {
+        match self.method {
+            Method::Morris { .. } => &["mu", "mu_star", "sigma"],
+            Method::Sobol => &["first_order", "total_order"],
+        }
+    }
+
+    /// Indices of each parameter, in the order of [Sensitivity::index_names], given the
+    /// values `y` of an output at the points of the `design`.
+    pub fn indices(&self, design: &[Vec<f64>], y: &[f64]) -> Vec<Vec<f64>> {
+        let k = self.parameters.len();
+        match self.method {
+            Method::Morris { levels } => {
+                // Elementary effects, the parameter of a step being the one that changed
+                let delta = morris_delta(levels);
+                let mut effects = vec![vec![]; k];
+                for trajectory in 0..self.samples {
+                    for step in 0..k {
+                        let j = trajectory * (k + 1) + step;
+                        let i = (0..k).find(|i| design[j + 1][*i] != design[j][*i]).unwrap();
+                        effects[i].push((y[j + 1] - y[j]) / delta);
+                    }
+                }
+                effects
+                    .iter()
+                    .map(|effects| {
+                        let n = effects.len() as f64;
+                        let mu = effects.iter().sum::<f64>() / n;
+                        let mu_star = effects.iter().map(|e| e.abs()).sum::<f64>() / n;
+                        let var = effects.iter().map(|e| (e - mu).powi(2)).sum::<f64>()
+                            / (n - 1.0).max(1.0);
+                        vec![mu, mu_star, var.sqrt()]
+                    })
+                    .collect()
+            }
+            Method::Sobol => {
+                // Saltelli estimator of the first order and Jansen one of the total order,
+                // on centered outputs to reduce the variance of the first order estimator
+                let block = k + 2;
+                let n = self.samples as f64;
+                let (f_a, f_b): (Vec<f64>, Vec<f64>) = (0..self.samples)
+                    .map(|j| (y[j * block], y[j * block + 1]))
+                    .unzip();
+                let mean = (f_a.iter().sum::<f64>() + f_b.iter().sum::<f64>()) / (2.0 * n);
+                let f_a: Vec<f64> = f_a.iter().map(|v| v - mean).collect();
+                let f_b: Vec<f64> = f_b.iter().map(|v| v - mean).collect();
+                let var = f_a.iter().chain(f_b.iter()).map(|v| v.powi(2)).sum::<f64>() / (2.0 * n);
+                (0..k)
+                    .map(|i| {
+                        if var <= 0.0 {
+                            return vec![0.0, 0.0];
+                        }
+                        let f_ab = |j: usize| y[j * block + 2 + i] - mean;
+                        let first = (0..self.samples)
+                            .map(|j| f_b[j] * (f_ab(j) - f_a[j]))
+                            .sum::<f64>()
+                            / n;
+                        let total = (0..self.samples)
+                            .map(|j| (f_a[j] - f_ab(j)).powi(2))
+                            .sum::<f64>()
+                            / (2.0 * n);
+                        vec![first / var, total / var]
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+fn morris_delta(levels: usize) -> f64 {
+    levels as f64 / (2.0 * (levels - 1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sensitivity(method: Method, samples: usize) -> Sensitivity {
+        Sensitivity {
+            method,
+            samples,
+            parameters: (0..3)
+                .map(|i| Parameter {
+                    path: format!("p{}", i),
+                    min: 0.0,
+                    max: 1.0,
+                    integer: false,
+                })
+                .collect(),
+            outputs: vec![],
+        }
+    }
+
+    /// Linear model of the first two parameters, the third having no effect.
+    fn model(design: &[Vec<f64>]) -> Vec<f64> {
+        design.iter().map(|u| 4.0 * u[0] + u[1]).collect()
+    }
+
+    #[test]
+    fn morris_effects_of_a_linear_model() {
+        let sensitivity = sensitivity(Method::Morris { levels: 4 }, 10);
+        let design = sensitivity.design(1);
+        assert_eq!(design.len(), 10 * 4);
+        let indices = sensitivity.indices(&design, &model(&design));
+        for (index, slope) in indices.iter().zip([4.0, 1.0, 0.0]) {
+            assert!((index[0] - slope).abs() < 1e-9);
+            assert!((index[1] - slope).abs() < 1e-9);
+            assert!(index[2].abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn sobol_indices_of_a_linear_model() {
+        let sensitivity = sensitivity(Method::Sobol, 20_000);
+        let design = sensitivity.design(1);
+        assert_eq!(design.len(), 20_000 * 5);
+        let indices = sensitivity.indices(&design, &model(&design));
+        // Variances of 16/12 and 1/12 for the first two parameters
+        for (index, expected) in indices.iter().zip([16.0 / 17.0, 1.0 / 17.0, 0.0]) {
+            assert!((index[0] - expected).abs() < 0.03, "{:?}", index);
+            assert!((index[1] - expected).abs() < 0.03, "{:?}", index);
+        }
+    }
+}
@@ -4,55 +4,64 @@ use indicatif::ProgressBar;
 use itertools::enumerate;
 use ndarray::{s, Array, Array1, Array2};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::collections::{HashMap, LinkedList};
-use std::fs::{self, OpenOptions};
+use std::collections::{BTreeMap, HashMap, LinkedList};
+use std::fs::{self, File};
 use std::io::Write;
 
+/// Analyzes a cohort of `count` units sharing the timeline of `token`.
 fn analyze_single_token(
     token: &Token,
+    count: u64,
     processes: &HashMap<u16, ActorLogInfos>,
     max_time: usize,
-) -> (Array1<f64>, Array1<f64>, Array2<u32>, Array2<u32>) {
-    let mut time: usize = 0;
-    let mut token_reentrances: Array2<u32> = Array::zeros((processes.len(), max_time));
-    let mut token_occupencies: Array2<u32> = Array::zeros((processes.len(), max_time));
+) -> (Array1<f64>, Array1<f64>, Array2<u64>, Array2<u64>) {
+    let mut token_reentrances: Array2<u64> = Array::zeros((processes.len(), max_time));
+    let mut token_occupencies: Array2<u64> = Array::zeros((processes.len(), max_time));
     let mut token_lifetimes: Array1<f64> = Array::zeros(processes.len());
-    for (index, code) in token.timeline.iter().enumerate() {
-        if index == 0 {
-            // The first value contains the number of executions of
-            // the production actor before this token was created
-            time += *code as usize;
-        } else if processes.contains_key(code) {
-            let actor_log_infos = processes.get(code).unwrap();
-            token_reentrances[[actor_log_infos.index, time]] += 1;
-            if let Some(delay_sampler) = &actor_log_infos.time_sampler {
-                let delay = delay_sampler();
-                let mut new_time = time + delay;
-                if new_time >= max_time {
-                    new_time = max_time - 1;
-                }
-                token_lifetimes[actor_log_infos.index] += delay as f64;
-                let mut s = token_occupencies.slice_mut(s![actor_log_infos.index, time..new_time]);
-                s += 1;
-                time = new_time;
-            }
+    for stamp in token.timeline.iter() {
+        let Some(actor_log_infos) = processes.get(&stamp.code) else {
+            continue;
+        };
+        if stamp.entry >= max_time {
+            continue;
         }
+        token_reentrances[[actor_log_infos.index, stamp.entry]] += count;
+        // Tokens that never left the actor occupy it until the end of the simulation
+        let exit = match stamp.exit {
+            Some(exit) => {
+                token_lifetimes[actor_log_infos.index] += (exit - stamp.entry) as f64;
+                exit.min(max_time)
+            }
+            None => max_time,
+        };
+        let mut s = token_occupencies.slice_mut(s![actor_log_infos.index, stamp.entry..exit]);
+        s += count;
     }
     (
-        token_lifetimes.clone(),
-        token_lifetimes.map(|x| x.powi(2)),
+        token_lifetimes.map(|x| x * count as f64),
+        token_lifetimes.map(|x| x.powi(2) * count as f64),
         token_reentrances,
         token_occupencies,
     )
 }
 
-pub fn analyze_timeline(
+/// Statistics of the tokens of one run, per logged actor and product.
+pub struct Timeline {
+    /// Mean and standard error of the time spent in each actor
+    pub lifetimes: Vec<(f64, f64)>,
+    pub reentrances: Array2<u64>,
+    pub occupencies: Array2<u64>,
+    /// Number of units per number of entries in each actor
+    pub cycles: Vec<BTreeMap<usize, u64>>,
+}
+
+pub fn compute_timeline(
     tokens: LinkedList<Token>,
     processes: &HashMap<u16, ActorLogInfos>,
     max_time: usize,
-    logs_folder: String,
-    dt: f64,
-) {
+) -> Timeline {
+    // Parts embedded in composite tokens are analyzed as tokens on their own
+    let tokens: Vec<(&Token, u64)> = tokens.iter().flat_map(|t| t.flatten()).collect();
     let bar = ProgressBar::new(tokens.len() as u64);
     let (sum_lifetimes, sum_lifetimes_s, all_reentrances, all_occupencies) = tokens
         .par_iter()
@@ -60,8 +69,8 @@ pub fn analyze_timeline(
             || {
                 let sum_lifetimes: Array1<f64> = Array::zeros(processes.len());
                 let sum_lifetimes_s: Array1<f64> = Array::zeros(processes.len());
-                let all_reentrances: Array2<u32> = Array::zeros((processes.len(), max_time));
-                let all_occupencies: Array2<u32> = Array::zeros((processes.len(), max_time));
+                let all_reentrances: Array2<u64> = Array::zeros((processes.len(), max_time));
+                let all_occupencies: Array2<u64> = Array::zeros((processes.len(), max_time));
                 (
                     sum_lifetimes,
                     sum_lifetimes_s,
@@ -69,9 +78,9 @@ pub fn analyze_timeline(
                     all_occupencies,
                 )
             },
-            |(sum_lifetimes, sum_lifetimes_s, all_reentrances, all_occupencies), token| {
+            |(sum_lifetimes, sum_lifetimes_s, all_reentrances, all_occupencies), (token, count)| {
                 let (token_lifetimes, token_lifetimes_s, token_reentrances, token_occupencies) =
-                    analyze_single_token(token, processes, max_time);
+                    analyze_single_token(token, *count, processes, max_time);
                 bar.inc(1);
                 (
                     sum_lifetimes + token_lifetimes,
@@ -85,8 +94,8 @@ pub fn analyze_timeline(
             || {
                 let acc_lifetimes: Array1<f64> = Array::zeros(processes.len());
                 let acc_lifetimes_s: Array1<f64> = Array::zeros(processes.len());
-                let acc_reentrances: Array2<u32> = Array::zeros((processes.len(), max_time));
-                let acc_occupencies: Array2<u32> = Array::zeros((processes.len(), max_time));
+                let acc_reentrances: Array2<u64> = Array::zeros((processes.len(), max_time));
+                let acc_occupencies: Array2<u64> = Array::zeros((processes.len(), max_time));
                 (
                     acc_lifetimes,
                     acc_lifetimes_s,
@@ -104,45 +113,213 @@ pub fn analyze_timeline(
                 )
             },
         );
-    let n = tokens.len() as f64;
-    let mean_lifetimes = sum_lifetimes.clone() / n;
-    let var_lifetimes = sum_lifetimes_s / n - mean_lifetimes.map(|x| x.powi(2));
+    // Distribution of the number of entries of the units in each actor
+    let mut cycles: HashMap<u16, BTreeMap<usize, u64>> = HashMap::new();
+    for (token, count) in tokens.iter() {
+        let mut visits: HashMap<u16, usize> = HashMap::new();
+        for stamp in token.timeline.iter() {
+            if processes.contains_key(&stamp.code) {
+                *visits.entry(stamp.code).or_default() += 1;
+            }
+        }
+        for (code, visits) in visits {
+            *cycles.entry(code).or_default().entry(visits).or_default() += count;
+        }
+    }
+    let mut lifetimes = vec![(0.0, 0.0); processes.len()];
+    let mut cycles_per_process = vec![BTreeMap::new(); processes.len()];
+    for (code, actor_log_infos) in processes.iter() {
+        let n = tokens
+            .iter()
+            .filter(|(t, _)| t.code == actor_log_infos.component)
+            .map(|(_, count)| count)
+            .sum::<u64>()
+            .max(1) as f64;
+        let mean_lifetime = sum_lifetimes[actor_log_infos.index] / n;
+        let var_lifetime = sum_lifetimes_s[actor_log_infos.index] / n - mean_lifetime.powi(2);
+        lifetimes[actor_log_infos.index] = (mean_lifetime, (var_lifetime / n).sqrt());
+        cycles_per_process[actor_log_infos.index] = cycles.remove(code).unwrap_or_default();
+    }
+    Timeline {
+        lifetimes,
+        reentrances: all_reentrances,
+        occupencies: all_occupencies,
+        cycles: cycles_per_process,
+    }
+}
+
+fn create_file(logs_folder: &str, product_code: &str, name: &str) -> File {
+    fs::create_dir_all(format!("{}/{}", logs_folder, product_code)).unwrap();
+    File::create(format!("{}/{}/{}", logs_folder, product_code, name)).unwrap()
+}
+
+pub fn write_timeline(
+    timeline: &Timeline,
+    processes: &HashMap<u16, ActorLogInfos>,
+    logs_folder: &str,
+    dt: f64,
+) {
     for actor_log_infos in processes.values() {
+        let index = actor_log_infos.index;
+        let (mean_lifetime, std_error) = timeline.lifetimes[index];
         println!(
             "lifetime {}: {}±{}",
             actor_log_infos.product_code,
-            mean_lifetimes[actor_log_infos.index] * dt,
-            (var_lifetimes[actor_log_infos.index] / n).sqrt() * dt
+            mean_lifetime * dt,
+            std_error * dt
+        );
+        let mut reentrance_file = create_file(
+            logs_folder,
+            &actor_log_infos.product_code,
+            "reentrances.csv",
         );
-        fs::create_dir_all(format!("{}/{}", logs_folder, actor_log_infos.product_code)).unwrap();
-        let mut reentrance_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .append(false)
-            .open(format!(
-                "{}/{}/reentrances.csv",
-                logs_folder, actor_log_infos.product_code
-            ))
-            .unwrap();
         writeln!(reentrance_file, "time,quantity").unwrap();
-        for (time, quantity) in enumerate(all_reentrances.slice(s![actor_log_infos.index, ..])) {
+        for (time, quantity) in enumerate(timeline.reentrances.slice(s![index, ..])) {
             writeln!(reentrance_file, "{},{}", time, quantity).unwrap();
         }
+        let mut cycles_file = create_file(logs_folder, &actor_log_infos.product_code, "cycles.csv");
+        writeln!(cycles_file, "cycles,quantity").unwrap();
+        for (visits, quantity) in timeline.cycles[index].iter() {
+            writeln!(cycles_file, "{},{}", visits, quantity).unwrap();
+        }
         if actor_log_infos.time_sampler.is_some() {
-            let mut occupency_file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(false)
-                .open(format!(
-                    "{}/{}/occupency.csv",
-                    logs_folder, actor_log_infos.product_code
-                ))
-                .unwrap();
+            let mut occupency_file =
+                create_file(logs_folder, &actor_log_infos.product_code, "occupency.csv");
             writeln!(occupency_file, "time,quantity").unwrap();
-            for (time, quantity) in enumerate(all_occupencies.slice(s![actor_log_infos.index, ..]))
-            {
+            for (time, quantity) in enumerate(timeline.occupencies.slice(s![index, ..])) {
                 writeln!(occupency_file, "{},{}", time, quantity).unwrap();
             }
         }
     }
 }
+
+pub fn analyze_timeline(
+    tokens: LinkedList<Token>,
+    processes: &HashMap<u16, ActorLogInfos>,
+    max_time: usize,
+    logs_folder: String,
+    dt: f64,
+) {
+    let timeline = compute_timeline(tokens, processes, max_time);
+    write_timeline(&timeline, processes, &logs_folder, dt);
+}
+
+/// Value below which `p` percent of the `sorted` values fall, interpolating linearly
+/// between the closest ranks.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+/// Writes one line per key with the mean, the standard deviation and the percentiles of
+/// the values of the replications.
+fn write_statistics(
+    file: &mut File,
+    key: &str,
+    rows: impl Iterator<Item = (usize, Vec<f64>)>,
+    percentiles: &[f64],
+) {
+    let columns: Vec<String> = percentiles.iter().map(|p| format!(",p{}", p)).collect();
+    writeln!(file, "{},mean,std{}", key, columns.concat()).unwrap();
+    for (key, mut values) in rows {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        values.sort_by(|a, b| a.total_cmp(b));
+        let columns: Vec<String> = percentiles
+            .iter()
+            .map(|p| format!(",{}", percentile(&values, *p)))
+            .collect();
+        writeln!(file, "{},{},{}{}", key, mean, var.sqrt(), columns.concat()).unwrap();
+    }
+}
+
+/// Writes the statistics over the timelines of several replications of the simulation.
+pub fn write_replications(
+    timelines: &[Timeline],
+    processes: &HashMap<u16, ActorLogInfos>,
+    logs_folder: &str,
+    dt: f64,
+    percentiles: &[f64],
+) {
+    let n = timelines.len() as f64;
+    for actor_log_infos in processes.values() {
+        let index = actor_log_infos.index;
+        let lifetimes: Vec<f64> = timelines.iter().map(|t| t.lifetimes[index].0).collect();
+        let mean_lifetime = lifetimes.iter().sum::<f64>() / n;
+        let var_lifetime = lifetimes
+            .iter()
+            .map(|l| (l - mean_lifetime).powi(2))
+            .sum::<f64>()
+            / (n - 1.0).max(1.0);
+        println!(
+            "lifetime {}: {}±{}",
+            actor_log_infos.product_code,
+            mean_lifetime * dt,
+            var_lifetime.sqrt() * dt
+        );
+        let product_code = &actor_log_infos.product_code;
+        let series = |get: fn(&Timeline) -> &Array2<u64>| {
+            let max_time = get(&timelines[0]).shape()[1];
+            (0..max_time).map(move |time| {
+                let values = timelines
+                    .iter()
+                    .map(|t| get(t)[[index, time]] as f64)
+                    .collect();
+                (time, values)
+            })
+        };
+        let mut file = create_file(logs_folder, product_code, "reentrances.csv");
+        write_statistics(&mut file, "time", series(|t| &t.reentrances), percentiles);
+        if actor_log_infos.time_sampler.is_some() {
+            let mut file = create_file(logs_folder, product_code, "occupency.csv");
+            write_statistics(&mut file, "time", series(|t| &t.occupencies), percentiles);
+        }
+        let max_cycles = timelines
+            .iter()
+            .filter_map(|t| t.cycles[index].keys().last())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        let cycles = (1..=max_cycles).map(|visits| {
+            let values = timelines
+                .iter()
+                .map(|t| t.cycles[index].get(&visits).copied().unwrap_or(0) as f64)
+                .collect();
+            (visits, values)
+        });
+        let mut file = create_file(logs_folder, product_code, "cycles.csv");
+        write_statistics(&mut file, "cycles", cycles, percentiles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_interpolate_between_ranks() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 25.0), 1.75);
+        assert_eq!(percentile(&sorted, 50.0), 2.5);
+        assert_eq!(percentile(&sorted, 100.0), 4.0);
+    }
+
+    #[test]
+    fn statistics_have_a_column_per_percentile() {
+        let path = std::env::temp_dir().join("statistics.csv");
+        let mut file = File::create(&path).unwrap();
+        let rows = [(0, vec![5.0, 1.0, 4.0, 2.0, 3.0])].into_iter();
+        write_statistics(&mut file, "time", rows, &[5.0, 50.0, 95.0]);
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[0], "time,mean,std,p5,p50,p95");
+        let values: Vec<f64> = lines[1].split(',').map(|v| v.parse().unwrap()).collect();
+        let expected = [0.0, 3.0, 2.5f64.sqrt(), 1.2, 3.0, 4.8];
+        for (value, expected) in values.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-9, "{}", lines[1]);
+        }
+    }
+}
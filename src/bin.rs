@@ -1,64 +1,553 @@
-use clap::Parser;
-use std::collections::LinkedList;
+use clap::{Parser, Subcommand};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::{BTreeMap, HashMap, LinkedList};
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::ExitCode;
+use yaml_rust2::Yaml;
 
 use componentflow::{
-    analyzer::timeline::analyze_timeline,
-    engine::{actor::AMActor, tokens::Token},
+    analyzer::{
+        balance::{check_balances, component_units, compute_balances, write_balances},
+        flows::{label_flows, write_flows, Flow},
+        graph::{export_graph, GraphFormat},
+        markov::markov_chains,
+        mfa::{dynamic_mfa, residence_pdf, residual_pdf, write_mfa},
+        sensitivity::{Output, Sensitivity},
+        timeline::{compute_timeline, write_replications, write_timeline, Timeline},
+    },
+    engine::{
+        actor::AMActor,
+        scheduler::{run, Scheduler},
+        tokens::Token,
+    },
     parser::{
         actors_parser::import_default_actors,
+        sensitivity_parser::{parameter_value, parse_sensitivity},
+        sweep_parser::{format_value, parse_sweep, set_parameter, Sweep},
         time_distribution_parser::import_default_time_callbacks,
-        yaml_parser::{parse_config, Result},
+        yaml_parser::{
+            load_yaml, parse_config, parse_config_doc, ActorLogInfos, Config, ParseError, Result,
+            YamlParser,
+        },
     },
 };
-use threadpool::ThreadPool;
 
 #[derive(Parser, Debug, Clone)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Arguments {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to the Yaml configuration file
-    #[arg(short, long)]
-    pub config: String,
+    #[arg(short, long, required = true)]
+    pub config: Option<String>,
 
     /// Path to the output folder
-    #[arg(short, long)]
-    pub output: String,
+    #[arg(short, long, required = true)]
+    pub output: Option<String>,
+
+    /// Seed of the random streams, overrides the one of the configuration file
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of independent runs, overrides the one of the configuration file
+    #[arg(long)]
+    pub replications: Option<usize>,
+
+    /// Path to a Yaml file with a sweep section, overrides the one of the configuration
+    #[arg(long)]
+    pub sweep: Option<String>,
+
+    /// Path to a Yaml file with a sensitivity section, overrides the one of the
+    /// configuration
+    #[arg(long)]
+    pub sensitivity: Option<String>,
 }
 
-pub fn supply_loop(source: AMActor, _pool: ThreadPool, _times: u32) {
-    let need_new_supply = source.lock().unwrap().as_source().supply();
-    if need_new_supply {
-        supply_loop(source, _pool, _times + 1);
+impl Arguments {
+    /// Folder of the results, always given when running simulations.
+    fn output(&self) -> &str {
+        self.output.as_deref().unwrap()
     }
 }
 
-pub fn main() -> Result<()> {
-    import_default_actors();
-    import_default_time_callbacks();
-    let args = Arguments::parse();
-    let pool = ThreadPool::new(1);
-    let config = parse_config(args.config, pool.clone())?;
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Writes the network of actors of a configuration as a diagram
+    Graph {
+        /// Path to the Yaml configuration file
+        #[arg(short, long)]
+        config: String,
+
+        /// Diagram format, dot or mermaid
+        #[arg(short, long, default_value = "dot")]
+        format: String,
+
+        /// Path to the output file, the standard output if missing
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+/// Results of a run of the simulation.
+struct Run {
+    /// Totals of the actors at the end of the simulation
+    totals: BTreeMap<String, u64>,
+    flows: Vec<Flow>,
+    timeline: Timeline,
+}
+
+/// Runs the simulation of `config` and writes the reports of the actors, the flows
+/// between them and the mass balance in `output` if given. Fails if an actor stops the
+/// simulation or if the mass balance does not close.
+fn simulate(config: &Config, output: Option<&str>) -> Result<Run> {
     let sources: Vec<AMActor> = config
         .init_sources
         .iter()
         .map(|a| config.actors.get(a).unwrap().clone())
         .collect();
-    pool.clone().execute(move || {
-        sources
-            .into_iter()
-            .for_each(|a| supply_loop(a, config.pool.clone(), 0))
-    });
-    pool.join();
+    let labels: HashMap<u16, String> = config
+        .actors
+        .iter()
+        .map(|(label, actor)| (actor.lock().unwrap().code(), label.clone()))
+        .collect();
+    let components: HashMap<u16, String> = config
+        .components
+        .iter()
+        .map(|(label, code)| (*code, label.clone()))
+        .collect();
+    let max_time = (config.global.time_window as f64 / config.global.dt) as usize;
+    run(&config.scheduler, &sources, max_time)
+        .map_err(|error| ParseError::Simulation(error.describe(&labels, &components)))?;
+    let mut totals = BTreeMap::new();
+    let (mut created, mut held) = (BTreeMap::new(), BTreeMap::new());
     let mut tokens: LinkedList<Token> = LinkedList::new();
-    for (label, actor) in config.actors {
-        println!("#{label}: {}", actor.lock().unwrap().total());
-        tokens.append(&mut actor.lock().unwrap().tokens());
+    for (label, actor) in config.actors.iter() {
+        let mut actor = actor.lock().unwrap();
+        if let Some(output) = output {
+            actor.report(&format!("{}/{}", output, label));
+        }
+        totals.insert(label.clone(), actor.total());
+        created.insert(label.clone(), actor.created());
+        let mut actor_tokens = actor.tokens();
+        held.insert(label.clone(), component_units(&actor_tokens));
+        tokens.append(&mut actor_tokens);
+    }
+    let mut scheduler = config.scheduler.lock().unwrap();
+    let flows = label_flows(&scheduler.flows, &labels, &components);
+    if let Some(output) = output {
+        let bin = ((config.global.flow_bin / config.global.dt).round() as usize).max(1);
+        write_flows(&flows, bin, config.global.dt, output);
+    }
+    // Tokens of the deliveries still pending in the scheduler, scheduled past the end
+    let mut in_flight = scheduler.in_flight();
+    let balances = compute_balances(&created, &held, &component_units(&in_flight), &components);
+    if let Some(output) = output {
+        write_balances(&balances, output);
     }
-    analyze_timeline(
-        tokens,
+    check_balances(&balances)?;
+    tokens.append(&mut in_flight);
+    Ok(Run {
+        totals,
+        flows,
+        timeline: compute_timeline(tokens, &config.logs, max_time),
+    })
+}
+
+/// Prints the outcome of the absorbing Markov chain of each component next to the
+/// simulated flows, which differ when conditional, `overflow` or `residue` routes are
+/// taken.
+fn print_markov(config: &Config, run: &Run) {
+    let mut components: Vec<(&String, &u16)> = config.components.iter().collect();
+    components.sort_by_key(|(_, code)| **code);
+    let labels: Vec<String> = components.iter().map(|(l, _)| (*l).clone()).collect();
+    for chain in markov_chains(&config.graph, &labels) {
+        let code = config.components[&chain.component];
+        let mut inflow: HashMap<String, f64> = HashMap::new();
+        for (label, actor) in config.actors.iter() {
+            for (component, units) in actor.lock().unwrap().created() {
+                if component == code {
+                    *inflow.entry(label.clone()).or_default() += units as f64;
+                }
+            }
+        }
+        // Simulated entries are the units received or created by the actor
+        let entries = |label: &str| -> u64 {
+            let received: u64 = run
+                .flows
+                .iter()
+                .filter(|flow| flow.to == label && flow.component == chain.component)
+                .map(|flow| flow.quantity)
+                .sum();
+            received + inflow.get(label).copied().unwrap_or(0.0) as u64
+        };
+        let Some(solution) = chain.solve(&inflow) else {
+            println!("markov {}: some units are never absorbed", chain.component);
+            continue;
+        };
+        println!("markov {}:", chain.component);
+        for (j, label) in chain.transient.iter().enumerate() {
+            let product_code = format!("{}/{}", label, chain.component);
+            let simulated_cycles = config
+                .logs
+                .values()
+                .find(|infos| infos.product_code == product_code)
+                .map(|infos| {
+                    let cycles = &run.timeline.cycles[infos.index];
+                    let units: u64 = cycles.values().sum();
+                    let entries: u64 = cycles.iter().map(|(v, q)| *v as u64 * q).sum();
+                    format!("{:.2}", entries as f64 / units.max(1) as f64)
+                })
+                .unwrap_or_else(|| String::from("-"));
+            println!(
+                "  {}: {:.0} visits ({} simulated), {:.2} cycles ({} simulated)",
+                label,
+                solution.visits[j],
+                entries(label),
+                solution.cycles[j],
+                simulated_cycles
+            );
+        }
+        let total: f64 = inflow.values().sum::<f64>().max(1.0);
+        for (k, label) in chain.absorbing.iter().enumerate() {
+            let simulated = entries(label);
+            println!(
+                "  {}: {:.0} absorbed, p = {:.3} ({} simulated, p = {:.3})",
+                label,
+                solution.absorbed[k],
+                solution.absorbed[k] / total,
+                simulated,
+                simulated as f64 / total
+            );
+        }
+    }
+}
+
+/// Computes the dynamic MFA of each component, supplied with the units created during
+/// the run, then writes the curves of the logged actors next to their token curves and
+/// prints them next to the simulated ones. The initial stock of an actor enters it at
+/// the first timestep and leaves after the rest of its residence time given its age,
+/// other units are supplied when their creator sends them. Components whose residence
+/// or age distributions have no known cumulative distribution are skipped.
+fn run_mfa(config: &Config, run: &Run, output: &str) {
+    let max_time = run.timeline.reentrances.ncols();
+    'components: for (component, code) in config.components.iter() {
+        let logs: Vec<&ActorLogInfos> = config
+            .logs
+            .values()
+            .filter(|infos| infos.component == *code)
+            .collect();
+        if logs.is_empty() {
+            continue;
+        }
+        let mut residence: HashMap<String, Vec<f64>> = HashMap::new();
+        for infos in logs.iter() {
+            let Some(actor) = infos.product_code.strip_suffix(&format!("/{}", component)) else {
+                continue;
+            };
+            match (&infos.time_sampler, &infos.time_cdf) {
+                (None, _) => {}
+                (Some(_), Some(cdf)) => {
+                    residence.insert(String::from(actor), residence_pdf(cdf));
+                }
+                (Some(_), None) => {
+                    println!(
+                        "mfa {}: no cumulative distribution, skipped",
+                        infos.product_code
+                    );
+                    continue 'components;
+                }
+            }
+        }
+        let mut supply: HashMap<String, Vec<f64>> = HashMap::new();
+        let mut initial: HashMap<String, (f64, Vec<f64>)> = HashMap::new();
+        for node in config.graph.nodes.iter() {
+            let actor = config.actors[&node.label].lock().unwrap();
+            if let Some((stock_code, stock)) = actor.initial_stock() {
+                if stock_code == *code {
+                    let release = match (residence.get(&node.label), &stock.age_cdf) {
+                        (None, _) => vec![1.0],
+                        (Some(pdf), Some(age)) => residual_pdf(&residence_pdf(age), pdf),
+                        (Some(_), None) => {
+                            println!(
+                                "mfa {}: no cumulative distribution of the age, skipped",
+                                node.label
+                            );
+                            continue 'components;
+                        }
+                    };
+                    initial.insert(node.label.clone(), (stock.quantity as f64, release));
+                    continue;
+                }
+            }
+            let created: u64 = actor
+                .created()
+                .iter()
+                .filter(|(c, _)| c == code)
+                .map(|(_, units)| units)
+                .sum();
+            if created == 0 {
+                continue;
+            }
+            let mut units = vec![0.0; max_time];
+            for flow in run.flows.iter() {
+                if flow.from == node.label && flow.component == *component {
+                    for (time, quantity) in flow.timeline.range(..max_time) {
+                        units[*time] += *quantity as f64;
+                    }
+                }
+            }
+            supply.insert(node.label.clone(), units);
+        }
+        let curves = dynamic_mfa(
+            &config.graph,
+            component,
+            &residence,
+            &supply,
+            &initial,
+            max_time,
+        );
+        for curves in curves {
+            let product_code = format!("{}/{}", curves.actor, curves.component);
+            let Some(infos) = logs.iter().find(|i| i.product_code == product_code) else {
+                continue;
+            };
+            write_mfa(&curves, output);
+            let entries: u64 = run.timeline.reentrances.row(infos.index).sum();
+            let stock = run.timeline.occupencies[[infos.index, max_time - 1]];
+            println!(
+                "mfa {}: {:.0} entries ({} simulated), final stock {:.0} ({} simulated)",
+                product_code,
+                curves.inflow.iter().sum::<f64>(),
+                entries,
+                curves.stock[max_time - 1],
+                stock
+            );
+        }
+    }
+}
+
+/// Runs the replications of the configuration `doc` into the `output` folder.
+fn run_config(doc: &Yaml, args: &Arguments, output: &str) -> Result<()> {
+    let config = parse_config_doc(doc, Scheduler::new())?;
+    if let Some(seed) = args.seed {
+        config.scheduler.lock().unwrap().seed = seed;
+    }
+    let seed = config.scheduler.lock().unwrap().seed;
+    println!("seed: {}", seed);
+    let replications = args.replications.unwrap_or(config.global.replications);
+    if replications <= 1 {
+        let run = simulate(&config, Some(output))?;
+        for flow in run.flows.iter() {
+            println!(
+                "{} -> {} ({}): {}",
+                flow.from, flow.to, flow.component, flow.quantity
+            );
+        }
+        print_markov(&config, &run);
+        run_mfa(&config, &run, output);
+        write_timeline(&run.timeline, &config.logs, output, config.global.dt);
+        return Ok(());
+    }
+    // Each replication parses its own configuration to get independent actors, the
+    // random streams of replication r being seeded with seed + r
+    let runs = (0..replications)
+        .into_par_iter()
+        .map(|replication| {
+            let config = parse_config_doc(doc, Scheduler::new())?;
+            config.scheduler.lock().unwrap().seed = seed.wrapping_add(replication as u64);
+            let output = format!("{}/replication_{}", output, replication);
+            simulate(&config, Some(&output))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut flows: BTreeMap<(&str, &str, &str), u64> = BTreeMap::new();
+    for run in runs.iter() {
+        for flow in run.flows.iter() {
+            *flows
+                .entry((&flow.from, &flow.to, &flow.component))
+                .or_default() += flow.quantity;
+        }
+    }
+    for ((from, to, component), quantity) in flows {
+        println!(
+            "{} -> {} ({}): {}",
+            from,
+            to,
+            component,
+            quantity as f64 / replications as f64
+        );
+    }
+    let timelines: Vec<Timeline> = runs.into_iter().map(|run| run.timeline).collect();
+    write_replications(
+        &timelines,
         &config.logs,
-        (config.global.time_window as f64 / config.global.dt) as usize,
-        args.output,
+        output,
         config.global.dt,
+        &config.global.percentiles,
     );
     Ok(())
 }
+
+/// Runs each variant of the sweep into its own folder, the index mapping the runs to
+/// the values of the parameters.
+fn run_sweep(doc: &Yaml, sweep: Sweep, args: &Arguments) -> Result<()> {
+    fs::create_dir_all(args.output())?;
+    let mut index = File::create(format!("{}/index.csv", args.output()))?;
+    writeln!(index, "run,{}", sweep.parameters.join(","))?;
+    for (run, values) in sweep.variants.iter().enumerate() {
+        let values: Vec<String> = values.iter().map(format_value).collect();
+        writeln!(index, "run_{},{}", run, values.join(","))?;
+    }
+    let mut variants = vec![];
+    for values in sweep.variants {
+        let mut variant = doc.clone();
+        for (path, value) in sweep.parameters.iter().zip(values) {
+            set_parameter(&mut variant, path, value)?;
+        }
+        variants.push(variant);
+    }
+    for (run, variant) in variants.iter().enumerate() {
+        println!("run_{}", run);
+        run_config(variant, args, &format!("{}/run_{}", args.output(), run))?;
+    }
+    Ok(())
+}
+
+/// Runs the points of the design of the sensitivity analysis, all with the same seed so
+/// that differences only come from the parameters, and writes the outputs of the runs
+/// in `samples.csv` and the indices of the parameters in `sensitivity.csv`.
+fn run_sensitivity(doc: &Yaml, sensitivity: Sensitivity, args: &Arguments) -> Result<()> {
+    let config = parse_config_doc(doc, Scheduler::new())?;
+    for (_, output) in sensitivity.outputs.iter() {
+        let (known, label) = match output {
+            Output::Total(label) => (config.actors.contains_key(label), label),
+            Output::PeakStock(product_code) | Output::FinalStock(product_code) => (
+                config
+                    .logs
+                    .values()
+                    .any(|infos| infos.product_code == *product_code),
+                product_code,
+            ),
+        };
+        if !known {
+            return Err(ParseError::UnknownActor(label.clone()));
+        }
+    }
+    let seed = args.seed.unwrap_or(config.scheduler.lock().unwrap().seed);
+    println!("seed: {}", seed);
+    let design = sensitivity.design(seed);
+    let mut variants = vec![];
+    for point in design.iter() {
+        let mut variant = doc.clone();
+        for (parameter, u) in sensitivity.parameters.iter().zip(point) {
+            set_parameter(
+                &mut variant,
+                &parameter.path,
+                parameter_value(parameter, *u),
+            )?;
+        }
+        variants.push(variant);
+    }
+    let results = variants
+        .into_par_iter()
+        .map(|variant| {
+            let config = parse_config_doc(&variant, Scheduler::new())?;
+            config.scheduler.lock().unwrap().seed = seed;
+            let Run {
+                totals, timeline, ..
+            } = simulate(&config, None)?;
+            sensitivity
+                .outputs
+                .iter()
+                .map(|(name, output)| {
+                    output
+                        .evaluate(&totals, &timeline, &config.logs)
+                        .ok_or_else(|| ParseError::UnknownActor(name.clone()))
+                })
+                .collect::<Result<Vec<f64>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    fs::create_dir_all(args.output())?;
+    let parameters: Vec<&str> = sensitivity.parameters.iter().map(|p| &p.path[..]).collect();
+    let outputs: Vec<&str> = sensitivity.outputs.iter().map(|(n, _)| &n[..]).collect();
+    let mut samples = File::create(format!("{}/samples.csv", args.output()))?;
+    writeln!(
+        samples,
+        "run,{},{}",
+        parameters.join(","),
+        outputs.join(",")
+    )?;
+    for (run, (point, values)) in design.iter().zip(results.iter()).enumerate() {
+        let point: Vec<String> = sensitivity
+            .parameters
+            .iter()
+            .zip(point)
+            .map(|(parameter, u)| parameter.value(*u).to_string())
+            .chain(values.iter().map(|v| v.to_string()))
+            .collect();
+        writeln!(samples, "{},{}", run, point.join(","))?;
+    }
+    let mut indices = File::create(format!("{}/sensitivity.csv", args.output()))?;
+    writeln!(
+        indices,
+        "output,parameter,{}",
+        sensitivity.index_names().join(",")
+    )?;
+    for (o, output) in outputs.iter().enumerate() {
+        let y: Vec<f64> = results.iter().map(|values| values[o]).collect();
+        for (parameter, values) in parameters.iter().zip(sensitivity.indices(&design, &y)) {
+            let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+            writeln!(indices, "{},{},{}", output, parameter, values.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+fn execute(args: &Arguments) -> Result<()> {
+    if let Some(Command::Graph {
+        config,
+        format,
+        output,
+    }) = &args.command
+    {
+        let config = parse_config(config.clone(), Scheduler::new())?;
+        let graph = export_graph(&config, GraphFormat::parse(format)?);
+        match output {
+            Some(output) => fs::write(output, graph)?,
+            None => print!("{}", graph),
+        }
+        return Ok(());
+    }
+    let doc = load_yaml(args.config.as_deref().unwrap())?;
+    let sensitivity = match &args.sensitivity {
+        Some(path) => Some(parse_sensitivity(load_yaml(path)?.get("sensitivity")?)?),
+        None if !doc["sensitivity"].is_badvalue() => Some(parse_sensitivity(&doc["sensitivity"])?),
+        None => None,
+    };
+    if let Some(sensitivity) = sensitivity {
+        return run_sensitivity(&doc, sensitivity, args);
+    }
+    let sweep = match &args.sweep {
+        Some(path) => Some(parse_sweep(load_yaml(path)?.get("sweep")?)?),
+        None if !doc["sweep"].is_badvalue() => Some(parse_sweep(&doc["sweep"])?),
+        None => None,
+    };
+    match sweep {
+        Some(sweep) => run_sweep(&doc, sweep, args),
+        None => run_config(&doc, args, args.output()),
+    }
+}
+
+pub fn main() -> ExitCode {
+    import_default_actors();
+    import_default_time_callbacks();
+    let args = Arguments::parse();
+    match execute(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
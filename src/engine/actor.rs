@@ -1,22 +1,44 @@
-use threadpool::ThreadPool;
 use yaml_rust2::Yaml;
 
 use crate::engine::fifo::Fifo;
-use crate::parser::yaml_parser::ParseError::UnknownComponent;
-use crate::parser::yaml_parser::{Result, YamlParser};
+use crate::parser::time_distribution_parser::{parse_time_cdf, parse_time_distribution};
+use crate::parser::yaml_parser::ParseError::{UnknownComponent, WrongFormat};
+use crate::parser::yaml_parser::{parse_time_series, read_time_series_csv, Result, YamlParser};
+use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::collections::{HashMap, LinkedList};
-use std::fs::OpenOptions;
+use std::collections::{BTreeMap, HashMap, LinkedList};
+use std::fs::{self, File};
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 
-use super::tokens::Token;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Binomial, Distribution, Normal, Poisson};
+
+use super::route::Route;
+use super::scheduler::{AMScheduler, Scheduler, SimulationError, SimulationResult};
+use super::time_series::TimeSeries;
+use super::tokens::{take_units, units, Stamp, Token};
+use crate::analyzer::mfa::{residence_pdf, residual_pdf};
+use crate::analyzer::{Cdf, Sampler};
 
 /// Associates a product code with a supply quantity
 pub struct SupplyOffer(pub String, pub u32);
 pub type AMActor = Arc<Mutex<dyn Actor + Send + Sync + 'static>>;
 pub type AMSource = Arc<Mutex<dyn Source + Send + Sync + 'static>>;
 
+/// Product code of the route followed by materials that are not recovered. It is
+/// declared as `residue` in the `clients` section.
+pub const RESIDUE: u16 = 0;
+
+/// Product code of the route followed by materials exceeding the capacity of an actor. It
+/// is declared as `overflow` in the `clients` section.
+pub const OVERFLOW: u16 = u16::MAX;
+
+/// Product code of the routes of a [LossActor] to its compartments. They are declared in
+/// its `compartments` section, with the loss rate as weight.
+pub const LOSS: u16 = u16::MAX - 1;
+
 /// [Actors][Actor] are nodes in a components flow graph. They produce new [Tokens][Token]
 /// from [Tokens][Token] stored in their storage, represented by [Fifos][Fifo].
 pub trait Actor {
@@ -24,15 +46,59 @@ pub trait Actor {
         doc: &Yaml,
         code: u16,
         components: HashMap<String, u16>,
-        pool: ThreadPool,
+        scheduler: AMScheduler,
     ) -> Result<AMActor>
     where
         Self: Sized;
-    /// Import tokens
-    fn import(&mut self, code_product: u16, tokens: LinkedList<Token>);
+    /// Import tokens, failing when the actor cannot handle them
+    fn import(
+        &mut self,
+        code_product: u16,
+        tokens: LinkedList<Token>,
+        time: usize,
+    ) -> SimulationResult<()>;
 
     /// Register a client callback for the specified product
-    fn register(&mut self, code: u16, code_product: u16, quantity: u32, actor: AMActor);
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor);
+
+    /// Sets how the actor splits tokens between its clients.
+    fn set_routing(&mut self, _routing: Routing) {}
+
+    /// Sets the time the specified product stays in the actor before being released, with
+    /// its cumulative distribution when known. Actors that cannot hold tokens ignore it.
+    fn set_residence_time(&mut self, _code_product: u16, _sampler: Sampler, _cdf: Option<Cdf>) {}
+
+    /// Whether the actor keeps all the tokens it receives, having no clients.
+    fn is_sink(&self) -> bool {
+        false
+    }
+
+    /// Components created by the actor rather than received from other actors.
+    fn products(&self) -> Vec<u16> {
+        vec![]
+    }
+
+    /// Units of each component created by the actor since the start of the run.
+    fn created(&self) -> Vec<(u16, u64)> {
+        vec![]
+    }
+
+    /// Components the actor sends to its clients, each needing a route of its own,
+    /// [RESIDUE] standing for the units it discards. Actors falling back on the
+    /// `residue` route return none.
+    fn outputs(&self) -> Vec<u16> {
+        vec![]
+    }
+
+    /// Whether the actor can take units of `component` from other actors.
+    fn accepts(&self, _component: u16) -> bool {
+        true
+    }
+
+    /// Component and units stored by the actor at the start of the run.
+    fn initial_stock(&self) -> Option<(u16, InitialStock)> {
+        None
+    }
 
     /// Resets the actor for a new run.
     fn reset(&mut self);
@@ -50,39 +116,172 @@ pub trait Actor {
 }
 
 pub trait Source: Actor {
-    fn supply(&mut self) -> bool;
+    /// Supplies the tokens produced at `time`. Returns false once the source is exhausted.
+    fn supply(&mut self, time: usize) -> SimulationResult<bool>;
+}
+
+/// Maximum number of draws of the residence time of a unit of the initial stock before
+/// considering it is already at the end of its life.
+pub const MAX_RESIDENCE_DRAWS: usize = 1000;
+
+/// Material already stored by an actor at the start of the simulation.
+#[derive(Clone)]
+pub struct InitialStock {
+    pub quantity: u64,
+    /// Distribution of the age of the units
+    pub age: Sampler,
+    /// Cumulative distribution of the age, when known for its distribution
+    pub age_cdf: Option<Cdf>,
+}
+
+/// Number of units of a cohort of `count` units released after each delay, given the
+/// probability `pdf` of each delay. Cohorts with fewer units than delays draw each unit
+/// from the cumulative probabilities, others draw a [multinomial] split.
+fn draw_delays(count: u64, pdf: &[f64], rng: &mut ChaCha8Rng) -> BTreeMap<usize, u64> {
+    let mut delays = BTreeMap::new();
+    if count >= pdf.len() as u64 {
+        for (delay, count) in multinomial(count, pdf, rng).into_iter().enumerate() {
+            if count > 0 {
+                delays.insert(delay, count);
+            }
+        }
+        return delays;
+    }
+    let cumulative: Vec<f64> = pdf
+        .iter()
+        .scan(0.0, |sum, p| {
+            *sum += p;
+            Some(*sum)
+        })
+        .collect();
+    let total = cumulative.last().copied().unwrap_or(0.0);
+    for _ in 0..count {
+        let u = rng.gen::<f64>() * total;
+        let delay = cumulative.partition_point(|c| *c <= u).min(pdf.len() - 1);
+        *delays.entry(delay).or_default() += 1;
+    }
+    delays
 }
 
+/// Holds each unit for its residence time before sending it to the clients of its
+/// component. Each component handled by the actor has its own [Fifo] and [Broadcast].
 pub struct SimpleActor {
     pub code: u16,
+    /// First component handled, the one of the initial stock
     pub code_product: u16,
-    pub import_fifo: Fifo,
-    client: AMActor,
-    pool: ThreadPool,
+    pub import_fifos: BTreeMap<u16, Fifo>,
+    pub residence_time: HashMap<u16, Sampler>,
+    /// Probability of each residence time in timesteps, for the components whose
+    /// distribution has a cumulative distribution
+    pub residence_pdf: HashMap<u16, Vec<f64>>,
+    /// Supplied at the first timestep, when the actor is used as a [Source]
+    pub initial_stock: Option<InitialStock>,
+    /// Share of quality lost by the tokens at each pass, such as in recycling
+    pub degradation: f64,
+    clients: HashMap<u16, AMActor>,
+    scheduler: AMScheduler,
     pub total: u64,
+    /// Units created, see [Actor::created]
+    pub created: u64,
 }
 
 impl SimpleActor {
-    pub fn new(code: u16, code_product: u16, pool: ThreadPool) -> SimpleActor {
-        let fifo: Fifo = Fifo::new(code, true);
+    pub fn new(code: u16, code_products: &[u16], scheduler: AMScheduler) -> SimpleActor {
         SimpleActor {
             code,
-            code_product,
-            import_fifo: fifo,
-            client: Broadcast::new(code, code_product, pool.clone()),
-            pool,
+            code_product: code_products[0],
+            import_fifos: code_products
+                .iter()
+                .map(|component| (*component, Fifo::new(code, true)))
+                .collect(),
+            residence_time: HashMap::new(),
+            residence_pdf: HashMap::new(),
+            initial_stock: None,
+            degradation: 0.0,
+            clients: code_products
+                .iter()
+                .map(|component| {
+                    let client = Broadcast::new(code, *component, scheduler.clone());
+                    (*component, client as AMActor)
+                })
+                .collect(),
+            scheduler,
             total: 0,
+            created: 0,
         }
     }
 
-    pub fn check_requirements(&mut self) {
-        if self.import_fifo.available_tokens() > 0 {
-            let tokens = self.import_fifo.get_all();
-            let code_product = self.code_product;
-            let client = self.client.clone();
-            self.pool.execute(move || {
-                (client.lock().unwrap()).import(code_product, tokens);
-            });
+    pub fn check_requirements(&mut self, time: usize) {
+        self.release(time, None);
+    }
+
+    /// Schedules the release of the stored units after their residence time. Units of the
+    /// initial `stock` have an age, and their residence time is drawn knowing they already
+    /// stayed that long. The delays of a cohort are drawn at once from the probability of
+    /// each delay when known, and unit by unit otherwise.
+    fn release(&mut self, time: usize, stock: Option<&InitialStock>) {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        for (component, fifo) in self.import_fifos.iter_mut() {
+            if fifo.available_tokens() == 0 {
+                continue;
+            }
+            let residence_time = self.residence_time.get(component);
+            let mut releases: BTreeMap<usize, LinkedList<Token>> = BTreeMap::new();
+            for mut token in fifo.get_all() {
+                let mut delays: BTreeMap<usize, u64> = BTreeMap::new();
+                match residence_time {
+                    None => {
+                        delays.insert(0, token.count);
+                    }
+                    Some(sampler) => {
+                        let mut rng = token.rng(scheduler.seed);
+                        let pdf = match (self.residence_pdf.get(component), stock) {
+                            (Some(pdf), None) => Some(Cow::Borrowed(pdf)),
+                            (Some(pdf), Some(stock)) => stock
+                                .age_cdf
+                                .as_ref()
+                                .map(|age| Cow::Owned(residual_pdf(&residence_pdf(age), pdf))),
+                            (None, _) => None,
+                        };
+                        match pdf {
+                            Some(pdf) => delays = draw_delays(token.count, &pdf, &mut rng),
+                            None => {
+                                for _ in 0..token.count {
+                                    let delay = match stock {
+                                        None => sampler(&mut rng),
+                                        Some(stock) => {
+                                            let age = (stock.age)(&mut rng);
+                                            (0..MAX_RESIDENCE_DRAWS)
+                                                .map(|_| sampler(&mut rng))
+                                                .find(|residence| *residence > age)
+                                                .map_or(0, |residence| residence - age)
+                                        }
+                                    };
+                                    *delays.entry(delay).or_default() += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+                // The cohort is only split between units released at different times
+                let Some((last_delay, _)) = delays.pop_last() else {
+                    continue;
+                };
+                for (delay, count) in delays {
+                    let mut cohort = token.split(count);
+                    cohort.leave(time + delay);
+                    releases.entry(time + delay).or_default().push_back(cohort);
+                }
+                token.leave(time + last_delay);
+                releases
+                    .entry(time + last_delay)
+                    .or_default()
+                    .push_back(token);
+            }
+            let client = self.clients.get(component).unwrap();
+            for (release, tokens) in releases {
+                scheduler.schedule(release, client.clone(), *component, tokens);
+            }
         }
     }
 }
@@ -92,19 +291,1376 @@ impl Actor for SimpleActor {
         self.code
     }
 
+    fn products(&self) -> Vec<u16> {
+        match self.initial_stock {
+            Some(_) => vec![self.code_product],
+            None => vec![],
+        }
+    }
+
+    fn created(&self) -> Vec<(u16, u64)> {
+        vec![(self.code_product, self.created)]
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        self.import_fifos.keys().copied().collect()
+    }
+
+    fn accepts(&self, component: u16) -> bool {
+        self.import_fifos.contains_key(&component)
+    }
+
+    fn initial_stock(&self) -> Option<(u16, InitialStock)> {
+        let stock = self.initial_stock.clone()?;
+        Some((self.code_product, stock))
+    }
+
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tokens(&mut self) -> LinkedList<Token> {
+        let mut tokens = LinkedList::new();
+        for fifo in self.import_fifos.values_mut() {
+            tokens.append(&mut fifo.get_all());
+        }
+        for client in self.clients.values() {
+            tokens.append(&mut client.lock().unwrap().tokens());
+        }
+        tokens
+    }
+
+    fn parse(
+        doc: &Yaml,
+        code: u16,
+        components: HashMap<String, u16>,
+        scheduler: AMScheduler,
+    ) -> Result<AMActor>
+    where
+        Self: Sized,
+    {
+        // A single component or a list of components
+        let component_doc = doc.get("component")?;
+        let labels = match component_doc.as_vec() {
+            Some(labels) => labels
+                .iter()
+                .map(|l| l.str())
+                .collect::<Result<Vec<&str>>>()?,
+            None => vec![component_doc.str()?],
+        };
+        let mut code_products = vec![];
+        for component in labels {
+            let code_product = components
+                .get(component)
+                .ok_or_else(|| UnknownComponent(String::from(component)))?;
+            code_products.push(*code_product);
+        }
+        if code_products.is_empty() {
+            return Err(WrongFormat(String::from(
+                "SimpleActor must handle at least one component",
+            )));
+        }
+        let dt = scheduler.lock().unwrap().dt;
+        let mut actor = SimpleActor::new(code, &code_products, scheduler);
+        if !doc["degradation"].is_badvalue() {
+            actor.degradation = doc["degradation"].number()?;
+        }
+        let stock_doc = &doc["initial_stock"];
+        if !stock_doc.is_badvalue() {
+            actor.initial_stock = Some(InitialStock {
+                quantity: stock_doc.get("quantity")?.int()? as u64,
+                age: parse_time_distribution(stock_doc.get("age")?, dt)?,
+                age_cdf: parse_time_cdf(stock_doc.get("age")?, dt).ok(),
+            });
+        }
+        Ok(Arc::new(Mutex::new(actor)))
+    }
+
+    fn as_source(&mut self) -> &mut dyn Source {
+        self
+    }
+
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        if let Some(token) = tokens.iter().find(|t| !self.accepts(t.code)) {
+            return Err(SimulationError::UnexpectedComponent(self.code, token.code));
+        }
+        self.total += units(&tokens);
+        let mut sorted: BTreeMap<u16, LinkedList<Token>> = BTreeMap::new();
+        for mut token in tokens {
+            token.quality *= 1.0 - self.degradation;
+            sorted.entry(token.code).or_default().push_back(token);
+        }
+        for (component, tokens) in sorted {
+            self.import_fifos
+                .get_mut(&component)
+                .unwrap()
+                .put(tokens, time);
+        }
+        self.check_requirements(time);
+        Ok(())
+    }
+
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        let (code_actor, scheduler) = (self.code, self.scheduler.clone());
+        self.clients
+            .entry(code_product)
+            .or_insert_with(|| Broadcast::new(code_actor, code_product, scheduler))
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        for client in self.clients.values() {
+            client.lock().unwrap().set_routing(routing);
+        }
+    }
+
+    fn set_residence_time(&mut self, component: u16, sampler: Sampler, cdf: Option<Cdf>) {
+        self.residence_time.insert(component, sampler);
+        if let Some(cdf) = cdf {
+            self.residence_pdf.insert(component, residence_pdf(&cdf));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.import_fifos.values_mut().for_each(|fifo| fifo.reset());
+    }
+
+    fn report(&self, _: &str) {}
+}
+
+impl Source for SimpleActor {
+    /// Stores the initial stock of the first component, whose units then leave according
+    /// to the residence time. The stock is supplied once, as [run][super::scheduler::run]
+    /// drops the source after.
+    fn supply(&mut self, time: usize) -> SimulationResult<bool> {
+        let Some(stock) = self.initial_stock.clone() else {
+            return Ok(false);
+        };
+        if stock.quantity > 0 {
+            let id = self.scheduler.lock().unwrap().new_ids(1).start;
+            let cohort = Token::new(id, self.code_product, stock.quantity, time);
+            self.total += stock.quantity;
+            self.created += stock.quantity;
+            self.import_fifos
+                .get_mut(&self.code_product)
+                .unwrap()
+                .put(LinkedList::from([cohort]), time);
+            self.release(time, Some(&stock));
+        }
+        Ok(false)
+    }
+}
+
+/// Processes at most `capacity` units per timestep, in their order of arrival. Units
+/// wait in a queue until processed. When an `overflow` route is registered, arrivals
+/// that would leave more than `max_queue` units waiting at the end of the timestep are
+/// sent to it instead, no `max_queue` meaning no unit may wait.
+pub struct CapacityActor {
+    pub code: u16,
+    pub code_product: u16,
+    pub import_fifo: Fifo,
+    /// Units processed per timestep
+    pub capacity: f64,
+    pub max_queue: Option<u64>,
+    client: AMActor,
+    overflow: Option<AMActor>,
+    /// Time at which the units queued so far will all be processed
+    busy_until: f64,
+    /// Units accepted, processed and overflowed at each timestep
+    arrivals: BTreeMap<usize, u64>,
+    departures: BTreeMap<usize, u64>,
+    overflows: BTreeMap<usize, u64>,
+    scheduler: AMScheduler,
+    pub total: u64,
+}
+
+impl CapacityActor {
+    pub fn new(code: u16, code_product: u16, capacity: f64, scheduler: AMScheduler) -> Self {
+        Self {
+            code,
+            code_product,
+            import_fifo: Fifo::new(code, true),
+            capacity,
+            max_queue: None,
+            client: Broadcast::new(code, code_product, scheduler.clone()),
+            overflow: None,
+            busy_until: 0.0,
+            arrivals: BTreeMap::new(),
+            departures: BTreeMap::new(),
+            overflows: BTreeMap::new(),
+            scheduler,
+            total: 0,
+        }
+    }
+
+    /// Units that can still be accepted at `time` without leaving more than `max_queue`
+    /// units waiting at the end of the timestep.
+    fn free(&self, time: usize) -> u64 {
+        let start = self.busy_until.max(time as f64);
+        let free =
+            ((time + 1) as f64 - start) * self.capacity + self.max_queue.unwrap_or(0) as f64 + 1e-9;
+        free.floor().max(0.0) as u64
+    }
+
+    pub fn check_requirements(&mut self, time: usize) {
+        if self.import_fifo.available_tokens() == 0 {
+            return;
+        }
+        let mut scheduler = self.scheduler.lock().unwrap();
+        for mut token in self.import_fifo.get_all() {
+            // Arrivals only overflow when there is a route for them
+            let accepted = match &self.overflow {
+                Some(_) => min(token.count, self.free(time)),
+                None => token.count,
+            };
+            if accepted < token.count {
+                let mut overflow = if accepted == 0 {
+                    std::mem::replace(&mut token, Token::new(0, 0, 0, time))
+                } else {
+                    token.split(token.count - accepted)
+                };
+                *self.overflows.entry(time).or_default() += overflow.count;
+                overflow.leave(time);
+                let route = self.overflow.clone().unwrap();
+                scheduler.schedule(time, route, OVERFLOW, LinkedList::from([overflow]));
+            }
+            if accepted == 0 {
+                continue;
+            }
+            *self.arrivals.entry(time).or_default() += accepted;
+            // Units are processed one after the other, the k-th one leaving during the
+            // timestep in which `start + k / capacity` falls
+            let start = self.busy_until.max(time as f64);
+            self.busy_until = start + accepted as f64 / self.capacity;
+            let mut processed = 0;
+            let mut step = start.floor() as usize;
+            while processed < accepted {
+                let done = ((((step + 1) as f64 - start) * self.capacity + 1e-9).floor() as u64)
+                    .min(accepted);
+                let count = done - processed;
+                processed = done;
+                if count > 0 {
+                    *self.departures.entry(step).or_default() += count;
+                    let mut cohort = if processed == accepted {
+                        std::mem::replace(&mut token, Token::new(0, 0, 0, time))
+                    } else {
+                        token.split(count)
+                    };
+                    cohort.leave(step);
+                    scheduler.schedule(
+                        step,
+                        self.client.clone(),
+                        self.code_product,
+                        LinkedList::from([cohort]),
+                    );
+                }
+                step += 1;
+            }
+        }
+    }
+
+    /// Queue at the end of the timestep, utilisation and overflow at each timestep until
+    /// the last one where the actor was active.
+    fn statistics(&self) -> Vec<(usize, u64, f64, u64)> {
+        let last = self
+            .departures
+            .keys()
+            .chain(self.overflows.keys())
+            .max()
+            .copied();
+        let Some(last) = last else {
+            return vec![];
+        };
+        let mut queue = 0;
+        (0..=last)
+            .map(|time| {
+                let processed = self.departures.get(&time).copied().unwrap_or(0);
+                queue += self.arrivals.get(&time).copied().unwrap_or(0);
+                queue -= processed;
+                (
+                    time,
+                    queue,
+                    processed as f64 / self.capacity,
+                    self.overflows.get(&time).copied().unwrap_or(0),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Actor for CapacityActor {
+    fn code(&self) -> u16 {
+        self.code
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tokens(&mut self) -> LinkedList<Token> {
+        let mut tokens = self.import_fifo.get_all();
+        tokens.append(&mut self.client.lock().unwrap().tokens());
+        if let Some(overflow) = &self.overflow {
+            tokens.append(&mut overflow.lock().unwrap().tokens());
+        }
+        tokens
+    }
+
+    fn parse(
+        doc: &Yaml,
+        code: u16,
+        components: HashMap<String, u16>,
+        scheduler: AMScheduler,
+    ) -> Result<AMActor>
+    where
+        Self: Sized,
+    {
+        let component = doc.get("component")?.str()?;
+        let code_product = components
+            .get(component)
+            .ok_or_else(|| UnknownComponent(String::from(component)))?;
+        let dt = scheduler.lock().unwrap().dt;
+        // The capacity is given per unit of time
+        let capacity = doc.get("capacity")?.number()? * dt;
+        if capacity <= 0.0 {
+            return Err(WrongFormat(format!(
+                "Capacity of {} must be positive",
+                component
+            )));
+        }
+        let mut actor = CapacityActor::new(code, *code_product, capacity, scheduler);
+        if !doc["max_queue"].is_badvalue() {
+            actor.max_queue = Some(doc["max_queue"].int()? as u64);
+        }
+        Ok(Arc::new(Mutex::new(actor)))
+    }
+
+    fn as_source(&mut self) -> &mut dyn Source {
+        panic!("CapacityActor is not a source");
+    }
+
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        self.total += units(&tokens);
+        self.import_fifo.put(tokens, time);
+        self.check_requirements(time);
+        Ok(())
+    }
+
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        let client = if code_product == OVERFLOW {
+            let (code_actor, scheduler) = (self.code, self.scheduler.clone());
+            self.overflow
+                .get_or_insert_with(|| Broadcast::new(code_actor, OVERFLOW, scheduler))
+        } else {
+            &self.client
+        };
+        client
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        self.client.lock().unwrap().set_routing(routing);
+        if let Some(overflow) = &self.overflow {
+            overflow.lock().unwrap().set_routing(routing);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.import_fifo.reset();
+        self.busy_until = 0.0;
+        self.arrivals.clear();
+        self.departures.clear();
+        self.overflows.clear();
+    }
+
+    /// Writes the queue length, the utilisation and the overflow at each timestep where
+    /// the actor was active.
+    fn report(&self, log_folder: &str) {
+        fs::create_dir_all(log_folder).unwrap();
+        let mut file = File::create(format!("{}/capacity.csv", log_folder)).unwrap();
+        writeln!(file, "time,queue,utilisation,overflow").unwrap();
+        for (time, queue, utilisation, overflow) in self.statistics() {
+            writeln!(file, "{},{},{},{}", time, queue, utilisation, overflow).unwrap();
+        }
+    }
+}
+
+/// Builds tokens of `code_product` from a bill of materials. Each component of the
+/// recipe waits in its own [Fifo] until enough tokens are available to assemble a
+/// product. The consumed tokens are attached to the product as parts.
+pub struct AssemblyActor {
+    pub code: u16,
+    pub code_product: u16,
+    pub recipe: HashMap<u16, u64>,
+    pub import_fifos: HashMap<u16, Fifo>,
+    client: AMActor,
+    scheduler: AMScheduler,
+    pub total: u64,
+    /// Units created, see [Actor::created]
+    pub created: u64,
+}
+
+impl AssemblyActor {
+    pub fn new(
+        code: u16,
+        code_product: u16,
+        recipe: HashMap<u16, u64>,
+        scheduler: AMScheduler,
+    ) -> AssemblyActor {
+        let import_fifos = recipe
+            .keys()
+            .map(|component| (*component, Fifo::new(code, true)))
+            .collect();
+        AssemblyActor {
+            code,
+            code_product,
+            recipe,
+            import_fifos,
+            client: Broadcast::new(code, code_product, scheduler.clone()),
+            scheduler,
+            total: 0,
+            created: 0,
+        }
+    }
+
+    /// Number of products that can be assembled with the stored components.
+    fn available_products(&self) -> u64 {
+        self.recipe
+            .iter()
+            .map(|(component, quantity)| {
+                self.import_fifos.get(component).unwrap().available_tokens() / quantity
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn check_requirements(&mut self, time: usize) {
+        let num_products = self.available_products();
+        if num_products == 0 {
+            return;
+        }
+        let mut products = LinkedList::new();
+        let mut remaining = num_products;
+        while remaining > 0 {
+            // Largest batch of products whose parts each come from a single cohort
+            let batch = self
+                .recipe
+                .iter()
+                .map(|(component, quantity)| {
+                    let fifo = self.import_fifos.get(component).unwrap();
+                    fifo.tokens.back().unwrap().count / quantity
+                })
+                .min()
+                .unwrap()
+                .clamp(1, remaining);
+            let id = self.scheduler.lock().unwrap().new_ids(1).start;
+            let mut product = Token::new(id, self.code_product, batch, time);
+            for (component, quantity) in self.recipe.iter() {
+                let fifo = self.import_fifos.get_mut(component).unwrap();
+                let mut tokens = fifo.get(quantity * batch);
+                for t in tokens.iter_mut() {
+                    t.leave(time);
+                    // Parts are counted per unit of the product
+                    if batch > 1 {
+                        t.count = *quantity;
+                    }
+                }
+                product.add_part(*component, tokens);
+            }
+            products.push_back(product);
+            remaining -= batch;
+            self.created += batch;
+        }
+        self.scheduler.lock().unwrap().schedule(
+            time,
+            self.client.clone(),
+            self.code_product,
+            products,
+        );
+    }
+}
+
+impl Actor for AssemblyActor {
+    fn code(&self) -> u16 {
+        self.code
+    }
+
+    fn products(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn created(&self) -> Vec<(u16, u64)> {
+        vec![(self.code_product, self.created)]
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn accepts(&self, component: u16) -> bool {
+        self.recipe.contains_key(&component)
+    }
+
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tokens(&mut self) -> LinkedList<Token> {
+        let mut tokens = LinkedList::new();
+        for fifo in self.import_fifos.values_mut() {
+            tokens.append(&mut fifo.get_all());
+        }
+        tokens.append(&mut self.client.lock().unwrap().tokens());
+        tokens
+    }
+
+    fn parse(
+        doc: &Yaml,
+        code: u16,
+        components: HashMap<String, u16>,
+        scheduler: AMScheduler,
+    ) -> Result<AMActor>
+    where
+        Self: Sized,
+    {
+        let component = doc.get("component")?.str()?;
+        let code_product = components
+            .get(component)
+            .ok_or_else(|| UnknownComponent(String::from(component)))?;
+        let mut recipe = HashMap::new();
+        for (part_label, quantity) in doc.get("recipe")?.hash()? {
+            let part_label = part_label.str()?;
+            let code_part = components
+                .get(part_label)
+                .ok_or_else(|| UnknownComponent(String::from(part_label)))?;
+            recipe.insert(*code_part, quantity.int()? as u64);
+        }
+        if recipe.is_empty() || recipe.values().any(|q| *q == 0) {
+            return Err(WrongFormat(format!(
+                "Recipe of assembly {} must list at least one part with a positive quantity",
+                component
+            )));
+        }
+        Ok(Arc::new(Mutex::new(AssemblyActor::new(
+            code,
+            *code_product,
+            recipe,
+            scheduler,
+        ))))
+    }
+
+    fn as_source(&mut self) -> &mut dyn Source {
+        panic!("AssemblyActor is not a source");
+    }
+
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        if let Some(token) = tokens.iter().find(|t| !self.accepts(t.code)) {
+            return Err(SimulationError::UnexpectedComponent(self.code, token.code));
+        }
+        self.total += units(&tokens);
+        let mut sorted: BTreeMap<u16, LinkedList<Token>> = BTreeMap::new();
+        for token in tokens {
+            sorted.entry(token.code).or_default().push_back(token);
+        }
+        for (component, tokens) in sorted {
+            self.import_fifos
+                .get_mut(&component)
+                .unwrap()
+                .put(tokens, time);
+        }
+        self.check_requirements(time);
+        Ok(())
+    }
+
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        self.client
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        self.client.lock().unwrap().set_routing(routing);
+    }
+
+    fn reset(&mut self) {
+        for fifo in self.import_fifos.values_mut() {
+            fifo.reset();
+        }
+    }
+
+    fn report(&self, _: &str) {}
+}
+
+/// Takes composite [Tokens][Token] apart. Each recovered component is routed to its own
+/// clients, through one [Broadcast] per component. Parts that are not recovered, as well
+/// as the emptied products, follow the [RESIDUE] route.
+pub struct DisassemblyActor {
+    pub code: u16,
+    pub code_product: u16,
+    pub import_fifo: Fifo,
+    pub recovery: HashMap<u16, f64>,
+    recovery_carry: HashMap<u16, f64>,
+    pub stock: LinkedList<Token>,
+    clients: HashMap<u16, AMActor>,
+    scheduler: AMScheduler,
+    pub total: u64,
+}
+
+impl DisassemblyActor {
+    pub fn new(
+        code: u16,
+        code_product: u16,
+        recovery: HashMap<u16, f64>,
+        scheduler: AMScheduler,
+    ) -> DisassemblyActor {
+        DisassemblyActor {
+            code,
+            code_product,
+            import_fifo: Fifo::new(code, true),
+            recovery,
+            recovery_carry: HashMap::new(),
+            stock: LinkedList::new(),
+            clients: HashMap::new(),
+            scheduler,
+            total: 0,
+        }
+    }
+
+    /// Splits `tokens` between recovered and unrecovered parts. Fractional recoveries
+    /// are carried over to the next products so no token is lost by rounding.
+    fn recover(&mut self, code_part: u16, mut tokens: LinkedList<Token>) -> [LinkedList<Token>; 2] {
+        let efficiency = *self.recovery.get(&code_part).unwrap_or(&1.0);
+        let carry = self.recovery_carry.entry(code_part).or_insert(0.0);
+        let total = units(&tokens);
+        let expected = total as f64 * efficiency + *carry;
+        let recovered = (expected.floor() as u64).min(total);
+        *carry = expected - recovered as f64;
+        let residue = take_units(&mut tokens, total - recovered);
+        [tokens, residue]
+    }
+
+    /// Sends `tokens` to the clients of `code_product`, falling back on the residue
+    /// route. Tokens without any route stay in the actor.
+    fn dispatch(&mut self, code_product: u16, mut tokens: LinkedList<Token>, time: usize) {
+        if tokens.is_empty() {
+            return;
+        }
+        let client = self
+            .clients
+            .get(&code_product)
+            .or_else(|| self.clients.get(&RESIDUE))
+            .cloned();
+        match client {
+            Some(client) => {
+                tokens.iter_mut().for_each(|t| t.leave(time));
+                self.scheduler
+                    .lock()
+                    .unwrap()
+                    .schedule(time, client, code_product, tokens)
+            }
+            None => self.stock.append(&mut tokens),
+        }
+    }
+
+    pub fn check_requirements(&mut self, time: usize) {
+        if self.import_fifo.available_tokens() == 0 {
+            return;
+        }
+        let mut recovered: BTreeMap<u16, LinkedList<Token>> = BTreeMap::new();
+        let mut residues: LinkedList<Token> = LinkedList::new();
+        for mut product in self.import_fifo.get_all() {
+            for (code_part, mut parts) in std::mem::take(&mut product.parts) {
+                // Parts are counted per unit of the product
+                parts.iter_mut().for_each(|t| t.count *= product.count);
+                let [mut parts, mut residue] = self.recover(code_part, parts);
+                recovered.entry(code_part).or_default().append(&mut parts);
+                residues.append(&mut residue);
+            }
+            let product_route = if self.clients.contains_key(&product.code) {
+                recovered.entry(product.code).or_default()
+            } else {
+                &mut residues
+            };
+            product_route.push_back(product);
+        }
+        for (code_part, parts) in recovered {
+            self.dispatch(code_part, parts, time);
+        }
+        self.dispatch(RESIDUE, residues, time);
+    }
+}
+
+impl Actor for DisassemblyActor {
+    fn code(&self) -> u16 {
+        self.code
+    }
+
+    fn products(&self) -> Vec<u16> {
+        self.recovery.keys().copied().collect()
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        match self.recovery.values().any(|efficiency| *efficiency < 1.0) {
+            true => vec![RESIDUE],
+            false => vec![],
+        }
+    }
+
+    fn accepts(&self, component: u16) -> bool {
+        component == self.code_product
+    }
+
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tokens(&mut self) -> LinkedList<Token> {
+        let mut tokens = self.import_fifo.get_all();
+        tokens.append(&mut self.stock);
+        for client in self.clients.values() {
+            tokens.append(&mut client.lock().unwrap().tokens());
+        }
+        tokens
+    }
+
+    fn parse(
+        doc: &Yaml,
+        code: u16,
+        components: HashMap<String, u16>,
+        scheduler: AMScheduler,
+    ) -> Result<AMActor>
+    where
+        Self: Sized,
+    {
+        let component = doc.get("component")?.str()?;
+        let code_product = components
+            .get(component)
+            .ok_or_else(|| UnknownComponent(String::from(component)))?;
+        let mut recovery = HashMap::new();
+        let recovery_doc = &doc["recovery"];
+        if !recovery_doc.is_badvalue() {
+            for (part_label, efficiency) in recovery_doc.hash()? {
+                let part_label = part_label.str()?;
+                let code_part = components
+                    .get(part_label)
+                    .ok_or_else(|| UnknownComponent(String::from(part_label)))?;
+                let efficiency = efficiency.float()?;
+                if !(0.0..=1.0).contains(&efficiency) {
+                    return Err(WrongFormat(format!(
+                        "Recovery efficiency of {} must be between 0 and 1",
+                        part_label
+                    )));
+                }
+                recovery.insert(*code_part, efficiency);
+            }
+        }
+        Ok(Arc::new(Mutex::new(DisassemblyActor::new(
+            code,
+            *code_product,
+            recovery,
+            scheduler,
+        ))))
+    }
+
+    fn as_source(&mut self) -> &mut dyn Source {
+        panic!("DisassemblyActor is not a source");
+    }
+
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        // Other components would be emptied of parts they do not have
+        if let Some(token) = tokens.iter().find(|t| !self.accepts(t.code)) {
+            return Err(SimulationError::UnexpectedComponent(self.code, token.code));
+        }
+        self.total += units(&tokens);
+        self.import_fifo.put(tokens, time);
+        self.check_requirements(time);
+        Ok(())
+    }
+
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        let (code_actor, scheduler) = (self.code, self.scheduler.clone());
+        self.clients
+            .entry(code_product)
+            .or_insert_with(|| Broadcast::new(code_actor, code_product, scheduler))
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        for client in self.clients.values() {
+            client.lock().unwrap().set_routing(routing);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.import_fifo.reset();
+        self.stock = LinkedList::new();
+        self.recovery_carry.clear();
+    }
+
+    fn report(&self, _: &str) {}
+}
+
+/// Converts batches of `ratio.0` tokens of `code_input` into `ratio.1` tokens of
+/// `code_product`. Out of each batch, `loss` input tokens are not converted and follow
+/// the [RESIDUE] route. Converted tokens are kept in `consumed` so their history can
+/// still be analyzed, and their identifiers are recorded as origins of the products.
+pub struct TransformActor {
+    pub code: u16,
+    pub code_input: u16,
+    pub code_product: u16,
+    pub ratio: (u64, u64),
+    pub loss: u64,
+    pub import_fifo: Fifo,
+    /// Converted tokens, those without parts sharing the same history being merged
+    pub consumed: Vec<Token>,
+    /// Position in `consumed` of the merged tokens, by component, creation and history
+    consumed_index: HashMap<(u16, usize, Vec<Stamp>), usize>,
+    pub stock: LinkedList<Token>,
+    clients: HashMap<u16, AMActor>,
+    scheduler: AMScheduler,
+    pub total: u64,
+    /// Units created, see [Actor::created]
+    pub created: u64,
+}
+
+impl TransformActor {
+    pub fn new(
+        code: u16,
+        code_input: u16,
+        code_product: u16,
+        ratio: (u64, u64),
+        loss: u64,
+        scheduler: AMScheduler,
+    ) -> TransformActor {
+        TransformActor {
+            code,
+            code_input,
+            code_product,
+            ratio,
+            loss,
+            import_fifo: Fifo::new(code, true),
+            consumed: vec![],
+            consumed_index: HashMap::new(),
+            stock: LinkedList::new(),
+            clients: HashMap::new(),
+            scheduler,
+            total: 0,
+            created: 0,
+        }
+    }
+
+    fn dispatch(&mut self, code_product: u16, mut tokens: LinkedList<Token>, time: usize) {
+        if tokens.is_empty() {
+            return;
+        }
+        match self.clients.get(&code_product).cloned() {
+            Some(client) => {
+                tokens.iter_mut().for_each(|t| t.leave(time));
+                self.scheduler
+                    .lock()
+                    .unwrap()
+                    .schedule(time, client, code_product, tokens)
+            }
+            None => self.stock.append(&mut tokens),
+        }
+    }
+
+    /// Keeps the converted `tokens` for the analysis of their history only, so cohorts
+    /// with the same history are merged to keep their number bounded.
+    fn consume(&mut self, tokens: LinkedList<Token>) {
+        for token in tokens {
+            if !token.parts.is_empty() {
+                self.consumed.push(token);
+                continue;
+            }
+            let key = (token.code, token.created, token.timeline.clone());
+            match self.consumed_index.get(&key) {
+                Some(index) => self.consumed[*index].count += token.count,
+                None => {
+                    self.consumed_index.insert(key, self.consumed.len());
+                    self.consumed.push(token);
+                }
+            }
+        }
+    }
+
+    pub fn check_requirements(&mut self, time: usize) {
+        let (input, output) = self.ratio;
+        let num_batches = self.import_fifo.available_tokens() / input;
+        if num_batches == 0 {
+            return;
+        }
+        let mut converted = self.import_fifo.get(input * num_batches);
+        let residues = take_units(&mut converted, self.loss * num_batches);
+        let id = self.scheduler.lock().unwrap().new_ids(1).start;
+        let mut product = Token::new(id, self.code_product, output * num_batches, time);
+        self.created += product.count;
+        for token in converted.iter_mut() {
+            token.leave(time);
+            product.origins.push(token.id);
+        }
+        self.consume(converted);
+        self.dispatch(self.code_product, LinkedList::from([product]), time);
+        self.dispatch(RESIDUE, residues, time);
+    }
+}
+
+impl Actor for TransformActor {
+    fn code(&self) -> u16 {
+        self.code
+    }
+
+    fn products(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn created(&self) -> Vec<(u16, u64)> {
+        vec![(self.code_product, self.created)]
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        match self.loss {
+            0 => vec![self.code_product],
+            _ => vec![self.code_product, RESIDUE],
+        }
+    }
+
+    fn accepts(&self, component: u16) -> bool {
+        component == self.code_input
+    }
+
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tokens(&mut self) -> LinkedList<Token> {
+        let mut tokens = self.import_fifo.get_all();
+        tokens.append(&mut self.stock);
+        tokens.extend(self.consumed.drain(..));
+        self.consumed_index.clear();
+        for client in self.clients.values() {
+            tokens.append(&mut client.lock().unwrap().tokens());
+        }
+        tokens
+    }
+
+    fn parse(
+        doc: &Yaml,
+        code: u16,
+        components: HashMap<String, u16>,
+        scheduler: AMScheduler,
+    ) -> Result<AMActor>
+    where
+        Self: Sized,
+    {
+        let component = doc.get("component")?.str()?;
+        let code_product = components
+            .get(component)
+            .ok_or_else(|| UnknownComponent(String::from(component)))?;
+        let input = doc.get("input")?.str()?;
+        let code_input = components
+            .get(input)
+            .ok_or_else(|| UnknownComponent(String::from(input)))?;
+        let ratio = {
+            let ratio_doc = doc.get("ratio")?;
+            let input = ratio_doc.get("input")?.int()? as u64;
+            let output = ratio_doc.get("output")?.int()? as u64;
+            (input, output)
+        };
+        let loss = match &doc["loss"] {
+            Yaml::BadValue => 0,
+            loss => loss.int()? as u64,
+        };
+        if ratio.0 == 0 || ratio.1 == 0 || loss >= ratio.0 {
+            return Err(WrongFormat(format!(
+                "Transformation into {} needs positive ratios and a loss lower than its input",
+                component
+            )));
+        }
+        Ok(Arc::new(Mutex::new(TransformActor::new(
+            code,
+            *code_input,
+            *code_product,
+            ratio,
+            loss,
+            scheduler,
+        ))))
+    }
+
+    fn as_source(&mut self) -> &mut dyn Source {
+        panic!("TransformActor is not a source");
+    }
+
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        // Other components would be converted as if they were the input
+        if let Some(token) = tokens.iter().find(|t| !self.accepts(t.code)) {
+            return Err(SimulationError::UnexpectedComponent(self.code, token.code));
+        }
+        self.total += units(&tokens);
+        self.import_fifo.put(tokens, time);
+        self.check_requirements(time);
+        Ok(())
+    }
+
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        let (code_actor, scheduler) = (self.code, self.scheduler.clone());
+        self.clients
+            .entry(code_product)
+            .or_insert_with(|| Broadcast::new(code_actor, code_product, scheduler))
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        for client in self.clients.values() {
+            client.lock().unwrap().set_routing(routing);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.import_fifo.reset();
+        self.stock = LinkedList::new();
+        self.consumed.clear();
+        self.consumed_index.clear();
+    }
+
+    fn report(&self, _: &str) {}
+}
+
+/// Random variation of the quantity supplied by a [SimpleSource] around its schedule.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SupplyNoise {
+    Poisson,
+    /// Normal distribution with the given coefficient of variation, truncated at zero
+    Normal(f64),
+}
+
+impl SupplyNoise {
+    fn parse(doc: &Yaml) -> Result<SupplyNoise> {
+        if doc.as_str() == Some("poisson") {
+            return Ok(SupplyNoise::Poisson);
+        }
+        let cv = doc.get("normal")?.get("cv")?.number()?;
+        if !cv.is_finite() || cv < 0.0 {
+            return Err(WrongFormat(format!(
+                "The cv of a normal noise must be a positive number, not {}",
+                cv
+            )));
+        }
+        Ok(SupplyNoise::Normal(cv))
+    }
+
+    /// Quantity drawn around `mean`, None if the distribution cannot be built from it.
+    fn sample(&self, mean: f64, rng: &mut ChaCha8Rng) -> Option<u64> {
+        if !mean.is_finite() {
+            return None;
+        }
+        if mean <= 0.0 {
+            return Some(0);
+        }
+        match self {
+            SupplyNoise::Poisson => Some(Poisson::new(mean).ok()?.sample(rng) as u64),
+            SupplyNoise::Normal(cv) => Some(
+                Normal::new(mean, cv * mean)
+                    .ok()?
+                    .sample(rng)
+                    .max(0.0)
+                    .round() as u64,
+            ),
+        }
+    }
+}
+
+/// Supplies `quantity` units every `period` timesteps from `start` until `end` or until
+/// `max_production` units were supplied.
+pub struct SimpleSource {
+    pub code: u16,
+    pub code_product: u16,
+    /// Quantity supplied at each period, evaluated at the time of the supply
+    pub quantity: TimeSeries,
+    pub period: usize,
+    pub start: usize,
+    pub end: Option<usize>,
+    pub noise: Option<SupplyNoise>,
+    pub num_executions: usize,
+    pub total: u64,
+    pub max_production: u64,
+    pub client: AMActor,
+    scheduler: AMScheduler,
+}
+
+impl SimpleSource {
+    pub fn new(
+        code: u16,
+        code_product: u16,
+        quantity: TimeSeries,
+        period: usize,
+        max_production: u64,
+        scheduler: AMScheduler,
+    ) -> Self {
+        Self {
+            code,
+            code_product,
+            quantity,
+            period,
+            start: 0,
+            end: None,
+            noise: None,
+            max_production,
+            num_executions: 0,
+            total: 0,
+            client: Broadcast::new(code, code_product, scheduler.clone()),
+            scheduler,
+        }
+    }
+}
+
+impl Source for SimpleSource {
+    fn supply(&mut self, time: usize) -> SimulationResult<bool> {
+        if self.total >= self.max_production || self.end.is_some_and(|end| time >= end) {
+            return Ok(false);
+        }
+        if time < self.start || !(time - self.start).is_multiple_of(self.period) {
+            return Ok(true);
+        }
+        self.num_executions += 1;
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let seed = scheduler.seed;
+        let id = scheduler.new_ids(1).start;
+        let mut cohort = Token::new(id, self.code_product, 0, time);
+        let mean = self.quantity.at(time).max(0.0);
+        let quantity = match self.noise {
+            None => mean.round() as u64,
+            Some(noise) => noise
+                .sample(mean, &mut cohort.rng(seed))
+                .ok_or(SimulationError::InvalidSupply(self.code, time))?,
+        };
+        let quantity = min(self.max_production - self.total, quantity);
+        if quantity == 0 {
+            return Ok(true);
+        }
+        self.total += quantity;
+        cohort.count = quantity;
+        scheduler.schedule(
+            time,
+            self.client.clone(),
+            self.code_product,
+            LinkedList::from([cohort]),
+        );
+        Ok(true)
+    }
+}
+
+impl Actor for SimpleSource {
+    fn code(&self) -> u16 {
+        self.code
+    }
+
+    fn products(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn created(&self) -> Vec<(u16, u64)> {
+        vec![(self.code_product, self.total)]
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tokens(&mut self) -> LinkedList<Token> {
+        self.client.lock().unwrap().tokens()
+    }
+
+    fn parse(
+        doc: &Yaml,
+        code: u16,
+        components: HashMap<String, u16>,
+        scheduler: AMScheduler,
+    ) -> Result<AMActor>
+    where
+        Self: Sized,
+    {
+        let component = doc.get("component")?.str()?;
+        let code_product = components
+            .get(component)
+            .ok_or_else(|| UnknownComponent(String::from(component)))?;
+        let dt = scheduler.lock().unwrap().dt;
+        let timestep = |time: f64| (time / dt).round() as usize;
+        let speed_doc = doc.get("speed")?;
+        let period = max(1, timestep(speed_doc.get("time")?.number()?));
+        let quantity = parse_time_series(speed_doc.get("quantity")?, dt)?;
+        let max_production = match &doc["max_production"] {
+            Yaml::BadValue => u64::MAX,
+            max_production => max_production.int()? as u64,
+        };
+        let mut source = SimpleSource::new(
+            code,
+            *code_product,
+            quantity,
+            period,
+            max_production,
+            scheduler,
+        );
+        // `delay` is the former name of `start`
+        for key in ["delay", "start"] {
+            if !doc[key].is_badvalue() {
+                source.start = timestep(doc[key].number()?);
+            }
+        }
+        if !doc["end"].is_badvalue() {
+            source.end = Some(timestep(doc["end"].number()?));
+        }
+        if !doc["noise"].is_badvalue() {
+            source.noise = Some(SupplyNoise::parse(&doc["noise"])?);
+        }
+        Ok(Arc::new(Mutex::new(source)))
+    }
+
+    fn as_source(&mut self) -> &mut dyn Source {
+        self
+    }
+
+    fn import(&mut self, _: u16, _: LinkedList<Token>, _: usize) -> SimulationResult<()> {
+        panic!("A source should not be supplied")
+    }
+
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        self.client
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        self.client.lock().unwrap().set_routing(routing);
+    }
+
+    fn reset(&mut self) {
+        self.total = 0;
+        self.num_executions = 0;
+    }
+
+    fn report(&self, log_folder: &str) {
+        fs::create_dir_all(log_folder).unwrap();
+        let mut file = File::create(format!("{}/logs.csv", log_folder)).unwrap();
+        writeln!(file, "{};{};{{}}", self.code, self.total).unwrap();
+    }
+}
+
+/// Supplies units following a time series of rates read from a CSV file, such as yearly
+/// production statistics. Each `time,quantity` row gives the quantity supplied per unit
+/// of time from its time until the next row, the last row lasting as long as the
+/// previous one. Rates are resampled on the timestep grid, fractions of units being
+/// carried over to the next timestep.
+pub struct TimeSeriesSource {
+    pub code: u16,
+    pub code_product: u16,
+    /// Quantity supplied per unit of time
+    pub rate: TimeSeries,
+    /// First timestep after the end of the series
+    pub end: usize,
+    pub dt: f64,
+    pub total: u64,
+    pub client: AMActor,
+    carry: f64,
+    scheduler: AMScheduler,
+}
+
+impl TimeSeriesSource {
+    pub fn new(
+        code: u16,
+        code_product: u16,
+        rate: TimeSeries,
+        end: usize,
+        scheduler: AMScheduler,
+    ) -> Self {
+        let dt = scheduler.lock().unwrap().dt;
+        Self {
+            code,
+            code_product,
+            rate,
+            end,
+            dt,
+            total: 0,
+            client: Broadcast::new(code, code_product, scheduler.clone()),
+            carry: 0.0,
+            scheduler,
+        }
+    }
+}
+
+impl Source for TimeSeriesSource {
+    fn supply(&mut self, time: usize) -> SimulationResult<bool> {
+        if time >= self.end {
+            return Ok(false);
+        }
+        let quantity = self.rate.at(time).max(0.0) * self.dt + self.carry;
+        self.carry = quantity.fract();
+        let quantity = quantity as u64;
+        if quantity == 0 {
+            return Ok(true);
+        }
+        self.total += quantity;
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let id = scheduler.new_ids(1).start;
+        let cohort = Token::new(id, self.code_product, quantity, time);
+        scheduler.schedule(
+            time,
+            self.client.clone(),
+            self.code_product,
+            LinkedList::from([cohort]),
+        );
+        Ok(true)
+    }
+}
+
+impl Actor for TimeSeriesSource {
+    fn code(&self) -> u16 {
+        self.code
+    }
+
+    fn products(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
+    fn created(&self) -> Vec<(u16, u64)> {
+        vec![(self.code_product, self.total)]
+    }
+
+    fn outputs(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
     fn total(&self) -> u64 {
         self.total
     }
 
     fn tokens(&mut self) -> LinkedList<Token> {
-        self.import_fifo.tokens.split_off(0)
+        self.client.lock().unwrap().tokens()
     }
 
     fn parse(
         doc: &Yaml,
         code: u16,
         components: HashMap<String, u16>,
-        pool: ThreadPool,
+        scheduler: AMScheduler,
     ) -> Result<AMActor>
     where
         Self: Sized,
@@ -113,111 +1669,120 @@ impl Actor for SimpleActor {
         let code_product = components
             .get(component)
             .ok_or_else(|| UnknownComponent(String::from(component)))?;
-        Ok(Arc::new(Mutex::new(SimpleActor::new(
+        let path = doc.get("file")?.str()?;
+        let mut rows = read_time_series_csv(path)?;
+        if rows.is_empty() {
+            return Err(WrongFormat(format!("{} has no rows", path)));
+        }
+        rows.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        // Time of the series matching the start of the simulation, such as a year
+        let origin = match &doc["origin"] {
+            Yaml::BadValue => 0.0,
+            origin => origin.number()?,
+        };
+        let dt = scheduler.lock().unwrap().dt;
+        let timestep = |time: f64| ((time - origin).max(0.0) / dt).round() as usize;
+        let last_duration = match rows.len() {
+            1 => 1.0,
+            n => rows[n - 1].0 - rows[n - 2].0,
+        };
+        let end = timestep(rows[rows.len() - 1].0 + last_duration);
+        let mut points: Vec<(usize, f64)> = vec![];
+        for (time, quantity) in rows {
+            // Rows before the origin are superseded by the last of them
+            if points.last().is_some_and(|(t, _)| *t == timestep(time)) {
+                points.pop();
+            }
+            points.push((timestep(time), quantity));
+        }
+        let rate = match doc["interpolation"].as_str().unwrap_or("step") {
+            "step" => TimeSeries::Step(points),
+            "linear" => TimeSeries::Linear(points),
+            interpolation => {
+                return Err(WrongFormat(format!(
+                    "Unknown interpolation {}, expected step or linear",
+                    interpolation
+                )))
+            }
+        };
+        Ok(Arc::new(Mutex::new(TimeSeriesSource::new(
             code,
             *code_product,
-            pool,
+            rate,
+            end,
+            scheduler,
         ))))
     }
 
     fn as_source(&mut self) -> &mut dyn Source {
-        panic!("SimpleActor is not a source");
+        self
     }
 
-    fn import(&mut self, _: u16, tokens: LinkedList<Token>) {
-        self.total += tokens.len() as u64;
-        self.import_fifo.put(tokens);
-        self.check_requirements();
+    fn import(&mut self, _: u16, _: LinkedList<Token>, _: usize) -> SimulationResult<()> {
+        panic!("A source should not be supplied")
     }
 
-    fn register(&mut self, code: u16, code_product: u16, quantity: u32, actor: AMActor) {
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
         self.client
             .lock()
             .unwrap()
-            .register(code, code_product, quantity, actor);
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        self.client.lock().unwrap().set_routing(routing);
     }
 
     fn reset(&mut self) {
-        self.import_fifo.reset();
+        self.total = 0;
+        self.carry = 0.0;
     }
 
-    fn report(&self, _: &str) {}
+    fn report(&self, log_folder: &str) {
+        fs::create_dir_all(log_folder).unwrap();
+        let mut file = File::create(format!("{}/logs.csv", log_folder)).unwrap();
+        writeln!(file, "{};{};{{}}", self.code, self.total).unwrap();
+    }
 }
 
-pub struct SimpleSource {
+pub struct SimpleSink {
     pub code: u16,
     pub code_product: u16,
-    pub speed: (u32, u32),
-    pub num_executions: u16,
-    pub total: u32,
-    pub max_production: u32,
-    pub client: AMActor,
-    pool: ThreadPool,
+    pub import_fifo: Fifo,
 }
 
-impl SimpleSource {
-    pub fn new(
-        code: u16,
-        code_product: u16,
-        speed: (u32, u32),
-        max_production: u32,
-        pool: ThreadPool,
-    ) -> Self {
+impl SimpleSink {
+    pub fn new(code: u16, code_product: u16) -> Self {
         Self {
             code,
             code_product,
-            max_production,
-            speed,
-            num_executions: 0,
-            total: 0u32,
-            client: Broadcast::new(code, code_product, pool.clone()),
-            pool,
-        }
-    }
-}
-
-impl Source for SimpleSource {
-    fn supply(&mut self) -> bool {
-        let quantity = min(self.max_production - self.total, self.speed.0);
-        if quantity == 0 {
-            return false;
+            import_fifo: Fifo::new(code, true),
         }
-        self.total += quantity;
-        let client = self.client.clone();
-        let code_product = self.code_product;
-        let num_executions = self.num_executions;
-        self.pool.execute(move || {
-            (client.lock().unwrap()).import(
-                code_product,
-                LinkedList::from_iter(vec![
-                    Token::new(code_product, Some(num_executions));
-                    quantity as usize
-                ]),
-            )
-        });
-        self.num_executions += 1;
-        true
     }
 }
 
-impl Actor for SimpleSource {
+impl Actor for SimpleSink {
     fn code(&self) -> u16 {
         self.code
     }
 
+    fn is_sink(&self) -> bool {
+        true
+    }
+
     fn total(&self) -> u64 {
-        self.total as u64
+        self.import_fifo.available_tokens()
     }
 
     fn tokens(&mut self) -> LinkedList<Token> {
-        LinkedList::new()
+        self.import_fifo.get_all()
     }
 
     fn parse(
         doc: &Yaml,
         code: u16,
         components: HashMap<String, u16>,
-        pool: ThreadPool,
+        _: AMScheduler,
     ) -> Result<AMActor>
     where
         Self: Sized,
@@ -226,86 +1791,130 @@ impl Actor for SimpleSource {
         let code_product = components
             .get(component)
             .ok_or_else(|| UnknownComponent(String::from(component)))?;
-        let speed = {
-            let speed_doc = doc.get("speed")?;
-            let time = speed_doc.get("time")?.int()? as u32;
-            let quantity = speed_doc.get("quantity")?.int()? as u32;
-            (quantity, time)
-        };
-        let max_production = doc.get("max_production")?.int()? as u32;
-        Ok(Arc::new(Mutex::new(SimpleSource::new(
-            code,
-            *code_product,
-            speed,
-            max_production,
-            pool,
-        ))))
+        Ok(Arc::new(Mutex::new(SimpleSink::new(code, *code_product))))
     }
 
     fn as_source(&mut self) -> &mut dyn Source {
-        self
+        panic!("SimpleActor is not a source");
     }
 
-    fn import(&mut self, _: u16, _: LinkedList<Token>) {
-        panic!("A source should not be supplied")
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        self.import_fifo.put(tokens, time);
+        Ok(())
     }
 
-    fn register(&mut self, code: u16, code_product: u16, quantity: u32, actor: AMActor) {
-        self.client
-            .lock()
-            .unwrap()
-            .register(code, code_product, quantity, actor);
+    fn register(&mut self, _: u16, _: u16, _: Route, _: AMActor) {
+        panic!("Sink have no output");
     }
 
     fn reset(&mut self) {
-        self.total = 0;
-        self.num_executions = 0;
+        self.import_fifo.reset();
     }
 
     fn report(&self, log_folder: &str) {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(format!("{}/logs.csv", log_folder))
-            .unwrap();
-        writeln!(file, "{};{};{{}}", self.code, self.total).unwrap();
+        fs::create_dir_all(log_folder).unwrap();
+        let mut file = File::create(format!("{}/logs.csv", log_folder)).unwrap();
+        writeln!(
+            file,
+            "{};{}",
+            self.code,
+            self.import_fifo.available_tokens()
+        )
+        .unwrap();
     }
 }
 
-pub struct SimpleSink {
+/// Environmental compartment receiving the units lost by a [LossActor]. It is an actor
+/// of its own, usually a [SimpleSink].
+pub struct Compartment {
+    /// Share of the units passing through the actor lost into the compartment
+    pub rate: TimeSeries,
+    carry: f64,
+    actor: AMActor,
+}
+
+/// Removes a share of the units passing through it into environmental compartments,
+/// such as air, water or soil, declared as sinks. The other units are forwarded
+/// immediately to the clients.
+pub struct LossActor {
     pub code: u16,
     pub code_product: u16,
     pub import_fifo: Fifo,
+    /// Compartments by actor code
+    pub compartments: BTreeMap<u16, Compartment>,
+    client: AMActor,
+    scheduler: AMScheduler,
+    pub total: u64,
 }
 
-impl SimpleSink {
-    pub fn new(code: u16, code_product: u16) -> Self {
+impl LossActor {
+    pub fn new(code: u16, code_product: u16, scheduler: AMScheduler) -> Self {
         Self {
             code,
             code_product,
             import_fifo: Fifo::new(code, true),
+            compartments: BTreeMap::new(),
+            client: Broadcast::new(code, code_product, scheduler.clone()),
+            scheduler,
+            total: 0,
+        }
+    }
+
+    pub fn check_requirements(&mut self, time: usize) {
+        if self.import_fifo.available_tokens() == 0 {
+            return;
         }
+        let mut tokens = self.import_fifo.get_all();
+        let total = units(&tokens);
+        let mut remaining = total;
+        let mut scheduler = self.scheduler.lock().unwrap();
+        // Fractional losses are carried over to the next arrivals so no unit is lost by
+        // rounding
+        for (code, compartment) in self.compartments.iter_mut() {
+            let rate = compartment.rate.at(time).clamp(0.0, 1.0);
+            let expected = total as f64 * rate + compartment.carry;
+            let lost = (expected.floor() as u64).min(remaining);
+            compartment.carry = expected - lost as f64;
+            if lost == 0 {
+                continue;
+            }
+            remaining -= lost;
+            let mut lost = take_units(&mut tokens, lost);
+            for token in lost.iter_mut() {
+                token.leave(time);
+                scheduler.record_flow(self.code, *code, token.code, time, token.count);
+            }
+            scheduler.schedule(time, compartment.actor.clone(), self.code_product, lost);
+        }
+        tokens.iter_mut().for_each(|t| t.leave(time));
+        scheduler.schedule(time, self.client.clone(), self.code_product, tokens);
     }
 }
 
-impl Actor for SimpleSink {
+impl Actor for LossActor {
     fn code(&self) -> u16 {
         self.code
     }
 
+    fn outputs(&self) -> Vec<u16> {
+        vec![self.code_product]
+    }
+
     fn total(&self) -> u64 {
-        self.import_fifo.available_tokens() as u64
+        self.total
     }
 
     fn tokens(&mut self) -> LinkedList<Token> {
-        self.import_fifo.tokens.split_off(0)
+        let mut tokens = self.import_fifo.get_all();
+        tokens.append(&mut self.client.lock().unwrap().tokens());
+        tokens
     }
 
     fn parse(
         doc: &Yaml,
         code: u16,
         components: HashMap<String, u16>,
-        _: ThreadPool,
+        scheduler: AMScheduler,
     ) -> Result<AMActor>
     where
         Self: Sized,
@@ -314,71 +1923,185 @@ impl Actor for SimpleSink {
         let code_product = components
             .get(component)
             .ok_or_else(|| UnknownComponent(String::from(component)))?;
-        Ok(Arc::new(Mutex::new(SimpleSink::new(code, *code_product))))
+        // Compartments are registered with the clients, as they refer to other actors
+        doc.get("compartments")?.hash()?;
+        Ok(Arc::new(Mutex::new(LossActor::new(
+            code,
+            *code_product,
+            scheduler,
+        ))))
     }
 
     fn as_source(&mut self) -> &mut dyn Source {
-        panic!("SimpleActor is not a source");
+        panic!("LossActor is not a source");
     }
 
-    fn import(&mut self, _: u16, tokens: LinkedList<Token>) {
-        self.import_fifo.put(tokens);
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        self.total += units(&tokens);
+        self.import_fifo.put(tokens, time);
+        self.check_requirements(time);
+        Ok(())
     }
 
-    fn register(&mut self, _: u16, _: u16, _: u32, _: AMActor) {
-        panic!("Sink have no output");
+    fn register(&mut self, code: u16, code_product: u16, route: Route, actor: AMActor) {
+        if code_product == LOSS {
+            let compartment = Compartment {
+                rate: route.share,
+                carry: 0.0,
+                actor,
+            };
+            self.compartments.insert(code, compartment);
+            return;
+        }
+        self.client
+            .lock()
+            .unwrap()
+            .register(code, code_product, route, actor);
+    }
+
+    fn set_routing(&mut self, routing: Routing) {
+        self.client.lock().unwrap().set_routing(routing);
     }
 
     fn reset(&mut self) {
         self.import_fifo.reset();
+        self.compartments.values_mut().for_each(|c| c.carry = 0.0);
     }
 
-    fn report(&self, log_folder: &str) {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(format!("{}/logs.csv", log_folder))
-            .unwrap();
-        writeln!(file, "{};{}", self.code, self.import_fifo.tokens.len()).unwrap();
+    fn report(&self, _: &str) {}
+}
+
+/// How a [Broadcast] splits the tokens it receives between its clients.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Routing {
+    /// Proportional split following a rolling sequence of the client weights
+    #[default]
+    Deterministic,
+    /// Each unit draws its destination independently from the client weights
+    Stochastic,
+    /// One multinomial draw from the client weights for all the units available
+    Multinomial,
+}
+
+impl Routing {
+    pub fn parse(value: &str) -> Result<Routing> {
+        match value {
+            "deterministic" => Ok(Routing::Deterministic),
+            "stochastic" => Ok(Routing::Stochastic),
+            "multinomial" => Ok(Routing::Multinomial),
+            _ => Err(WrongFormat(format!(
+                "Unknown routing {}, expected deterministic, stochastic or multinomial",
+                value
+            ))),
+        }
+    }
+}
+
+/// Number of slots the client shares are spread over when they are not all integers.
+const ROUTING_RESOLUTION: u32 = 1000;
+
+/// Integer weights of the rolling sequence for the given `shares`. Integer shares are
+/// kept as they are. Others are normalised over [ROUTING_RESOLUTION] slots, allocated by
+/// largest remainder, then divided by their greatest common divisor to keep the
+/// sequence short.
+fn integer_weights(shares: &[f64]) -> Vec<u32> {
+    if shares.iter().all(|share| share.fract() == 0.0) {
+        return shares.iter().map(|share| *share as u32).collect();
     }
+    let total: f64 = shares.iter().sum();
+    let exact: Vec<f64> = shares
+        .iter()
+        .map(|share| share / total * ROUTING_RESOLUTION as f64)
+        .collect();
+    let mut weights: Vec<u32> = exact.iter().map(|e| e.floor() as u32).collect();
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.sort_by(|a, b| exact[*b].fract().total_cmp(&exact[*a].fract()));
+    let allocated: u32 = weights.iter().sum();
+    for index in order
+        .into_iter()
+        .take((ROUTING_RESOLUTION - allocated) as usize)
+    {
+        weights[index] += 1;
+    }
+    let divisor = weights.iter().fold(0, |a, b| gcd(a, *b)).max(1);
+    weights.iter().map(|w| w / divisor).collect()
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        b => gcd(b, a % b),
+    }
+}
+
+/// Splits `n` units between categories of the given `weights`, using one binomial draw
+/// per category conditioned on the units left.
+fn multinomial(n: u64, weights: &[f64], rng: &mut ChaCha8Rng) -> Vec<u64> {
+    let mut remaining = n;
+    let mut counts = Vec::with_capacity(weights.len());
+    // Weights left from each index, summed once as the weights may be long histograms
+    let mut remaining_weights = vec![0.0; weights.len()];
+    let mut sum = 0.0;
+    for (index, weight) in weights.iter().enumerate().rev() {
+        sum += weight;
+        remaining_weights[index] = sum;
+    }
+    for (weight, remaining_weight) in weights.iter().zip(remaining_weights) {
+        let count = if remaining == 0 || *weight <= 0.0 {
+            0
+        } else if *weight >= remaining_weight {
+            remaining
+        } else {
+            Binomial::new(remaining, weight / remaining_weight)
+                .unwrap()
+                .sample(rng)
+        };
+        counts.push(count);
+        remaining -= count;
+    }
+    counts
 }
 
 pub struct Broadcast {
     pub code: u16,
     pub code_product: u16,
     pub import_fifo: Fifo,
-    clients: HashMap<u16, (u32, AMActor)>,
-    quantity: u32,
-    pool: ThreadPool,
+    clients: BTreeMap<u16, (Route, AMActor)>,
+    /// Integer weights of the clients the rolling sequence was built from, see
+    /// [integer_weights]
+    weights: Vec<u32>,
+    scheduler: AMScheduler,
     rolling_sequence: Vec<u16>,
     rolling_index: usize,
+    routing: Routing,
 }
 
 impl Broadcast {
-    pub fn new(code: u16, code_product: u16, pool: ThreadPool) -> Arc<Mutex<Broadcast>> {
+    pub fn new(code: u16, code_product: u16, scheduler: AMScheduler) -> Arc<Mutex<Broadcast>> {
         Arc::new(Mutex::new(Self {
             code,
             code_product,
             import_fifo: Fifo::new(code, false),
-            clients: HashMap::new(),
-            quantity: 0,
-            pool,
+            clients: BTreeMap::new(),
+            weights: vec![],
+            scheduler,
             rolling_sequence: vec![],
             rolling_index: 0,
+            routing: Routing::default(),
         }))
     }
 
     pub fn create_rolling_sequence(&mut self) {
         let actors: Vec<u16> = self.clients.keys().copied().collect();
         let mut counts = vec![0; actors.len()];
-        let total = self.quantity as i32;
+        let total = self.weights.iter().sum::<u32>() as i32;
         let mut sequence = vec![];
         for _ in 0..total {
             let (index, _) = actors
                 .iter()
                 .enumerate()
-                .map(|(index, code)| {
-                    let probability = self.clients.get(code).unwrap().0 as i32;
+                .map(|(index, _)| {
+                    let probability = self.weights[index] as i32;
                     let sequence_length = max(1, sequence.len()) as i32;
                     let count = counts[index];
                     (index, probability * sequence_length - total * count)
@@ -391,26 +2114,151 @@ impl Broadcast {
         self.rolling_sequence = sequence;
     }
 
-    pub fn check_requirements(&mut self) {
+    /// Weights of the clients at `time`, in the order of their codes. Clients with a
+    /// condition only take the units matching it, so they have no weight. Fails when
+    /// none is positive, as the units would otherwise stay in the actor.
+    fn shares(&self, time: usize) -> SimulationResult<Vec<f64>> {
+        let shares: Vec<f64> = self
+            .clients
+            .values()
+            .map(|(route, _)| match route.condition {
+                Some(_) => 0.0,
+                None => route.share.at(time).max(0.0),
+            })
+            .collect();
+        match shares.iter().sum::<f64>() > 0.0 {
+            true => Ok(shares),
+            false => Err(SimulationError::NoClientWeight(self.code, time)),
+        }
+    }
+
+    pub fn check_requirements(&mut self, time: usize) -> SimulationResult<()> {
         if self.import_fifo.available_tokens() == 0 {
-            return;
+            return Ok(());
+        }
+        let has_conditions = self
+            .clients
+            .values()
+            .any(|(route, _)| route.condition.is_some());
+        if has_conditions {
+            self.route_conditions(time);
+            if self.import_fifo.available_tokens() == 0 {
+                return Ok(());
+            }
         }
-        if self.clients.len() == 1 {
-            let key = self.clients.keys().next().unwrap();
-            let (_, client) = self.clients.get(key).unwrap().clone();
+        if self.clients.len() == 1 && !has_conditions {
+            let (code, (_, client)) = self.clients.iter().next().unwrap();
             let tokens = self.import_fifo.get_all();
-            let code_product = self.code_product;
-            self.pool
-                .execute(move || client.lock().unwrap().import(code_product, tokens));
-            return;
+            self.send(
+                &mut self.scheduler.lock().unwrap(),
+                time,
+                *code,
+                client.clone(),
+                tokens,
+            );
+            return Ok(());
+        }
+        match self.routing {
+            Routing::Deterministic => self.route_rolling_sequence(time),
+            Routing::Stochastic => self.route_stochastic(time),
+            Routing::Multinomial => self.route_multinomial(time),
+        }
+    }
+
+    /// Schedules the delivery of `tokens` to the client of code `code`, recording the
+    /// units flowing from the actor to the client.
+    fn send(
+        &self,
+        scheduler: &mut Scheduler,
+        time: usize,
+        code: u16,
+        client: AMActor,
+        tokens: LinkedList<Token>,
+    ) {
+        for token in tokens.iter() {
+            scheduler.record_flow(self.code, code, token.code, time, token.count);
+        }
+        scheduler.schedule(time, client, self.code_product, tokens);
+    }
+
+    /// Sends the cohorts matching the condition of a client to it, the first matching
+    /// client in the order of their codes. The other cohorts are left to the weighted
+    /// split.
+    fn route_conditions(&mut self, time: usize) {
+        let mut routed: Vec<LinkedList<Token>> = vec![LinkedList::new(); self.clients.len()];
+        let mut remaining = LinkedList::new();
+        for token in self.import_fifo.get_all() {
+            match self
+                .clients
+                .values()
+                .position(|(route, _)| route.matches(&token, time))
+            {
+                Some(index) => routed[index].push_back(token),
+                None => remaining.push_back(token),
+            }
+        }
+        self.import_fifo.put(remaining, time);
+        let mut scheduler = self.scheduler.lock().unwrap();
+        for ((code, (_, client)), tokens) in self.clients.iter().zip(routed) {
+            self.send(&mut scheduler, time, *code, client.clone(), tokens);
+        }
+    }
+
+    /// Sends each cohort to the clients following one multinomial draw per cohort, which
+    /// amounts to independent draws for every unit.
+    fn route_stochastic(&mut self, time: usize) -> SimulationResult<()> {
+        let weights = self.shares(time)?;
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let seed = scheduler.seed;
+        let mut routed: Vec<LinkedList<Token>> = vec![LinkedList::new(); weights.len()];
+        for mut token in self.import_fifo.get_all() {
+            let counts = multinomial(token.count, &weights, &mut token.rng(seed));
+            let Some(last) = counts.iter().rposition(|c| *c > 0) else {
+                continue;
+            };
+            for (index, count) in counts.into_iter().enumerate().take(last) {
+                if count > 0 {
+                    routed[index].push_back(token.split(count));
+                }
+            }
+            routed[last].push_back(token);
+        }
+        for ((code, (_, client)), tokens) in self.clients.iter().zip(routed) {
+            self.send(&mut scheduler, time, *code, client.clone(), tokens);
+        }
+        Ok(())
+    }
+
+    /// Splits all the units available with a single multinomial draw, using the random
+    /// stream of the oldest cohort.
+    fn route_multinomial(&mut self, time: usize) -> SimulationResult<()> {
+        let weights = self.shares(time)?;
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let seed = scheduler.seed;
+        let mut rng = self.import_fifo.tokens.back_mut().unwrap().rng(seed);
+        let counts = multinomial(self.import_fifo.available_tokens(), &weights, &mut rng);
+        for ((code, (_, client)), count) in self.clients.iter().zip(counts) {
+            let tokens = self.import_fifo.get(count);
+            self.send(&mut scheduler, time, *code, client.clone(), tokens);
+        }
+        Ok(())
+    }
+
+    fn route_rolling_sequence(&mut self, time: usize) -> SimulationResult<()> {
+        // The sequence is rebuilt whenever the integer weights change over time
+        let weights = integer_weights(&self.shares(time)?);
+        if weights != self.weights {
+            self.weights = weights;
+            self.create_rolling_sequence();
+            self.rolling_index = 0;
         }
         let num_full_activations =
-            self.import_fifo.available_tokens() / self.rolling_sequence.len() as u32;
+            self.import_fifo.available_tokens() / self.rolling_sequence.len() as u64;
         let remaining_tokens =
-            self.import_fifo.available_tokens() % self.rolling_sequence.len() as u32;
+            self.import_fifo.available_tokens() % self.rolling_sequence.len() as u64;
 
-        for (code, (q, a)) in self.clients.iter() {
-            let a = a.clone();
+        let mut scheduler = self.scheduler.lock().unwrap();
+        for (index, (code, (_, a))) in self.clients.iter().enumerate() {
             let remaining_number = self
                 .rolling_sequence
                 .iter()
@@ -419,17 +2267,16 @@ impl Broadcast {
                 .take(remaining_tokens as usize)
                 .filter(|c| c == &code)
                 .collect::<Vec<&u16>>()
-                .len() as u32;
+                .len() as u64;
 
             let tokens = self
                 .import_fifo
-                .get(q * num_full_activations + remaining_number);
-            let code_product = self.code_product;
-            self.pool
-                .execute(move || a.lock().unwrap().import(code_product, tokens));
+                .get(self.weights[index] as u64 * num_full_activations + remaining_number);
+            self.send(&mut scheduler, time, *code, a.clone(), tokens);
         }
         self.rolling_index =
             (self.rolling_index + remaining_tokens as usize) % self.rolling_sequence.len();
+        Ok(())
     }
 }
 
@@ -439,14 +2286,14 @@ impl Actor for Broadcast {
     }
 
     fn total(&self) -> u64 {
-        self.import_fifo.available_tokens() as u64
+        self.import_fifo.available_tokens()
     }
 
     fn tokens(&mut self) -> LinkedList<Token> {
-        self.import_fifo.tokens.split_off(0)
+        self.import_fifo.get_all()
     }
 
-    fn parse(_: &Yaml, _: u16, _: HashMap<String, u16>, _: ThreadPool) -> Result<AMActor>
+    fn parse(_: &Yaml, _: u16, _: HashMap<String, u16>, _: AMScheduler) -> Result<AMActor>
     where
         Self: Sized,
     {
@@ -457,20 +2304,19 @@ impl Actor for Broadcast {
         panic!("SimpleActor is not a source");
     }
 
-    fn import(&mut self, _: u16, tokens: LinkedList<Token>) {
-        self.import_fifo.put(tokens);
-        self.check_requirements();
+    fn import(&mut self, _: u16, tokens: LinkedList<Token>, time: usize) -> SimulationResult<()> {
+        self.import_fifo.put(tokens, time);
+        self.check_requirements(time)
     }
 
-    fn register(&mut self, code: u16, _: u16, quantity: u32, actor: AMActor) {
-        let old = self.clients.insert(code, (quantity, actor));
-        if let Some((q, _)) = old {
-            self.quantity -= q;
-        }
-        self.quantity += quantity;
-        if self.clients.len() > 1 {
-            self.create_rolling_sequence();
-        }
+    fn set_routing(&mut self, routing: Routing) {
+        self.routing = routing;
+    }
+
+    fn register(&mut self, code: u16, _: u16, route: Route, actor: AMActor) {
+        self.clients.insert(code, (route, actor));
+        // The rolling sequence is built on the next activation
+        self.weights.clear();
     }
 
     fn reset(&mut self) {
@@ -479,3 +2325,618 @@ impl Actor for Broadcast {
 
     fn report(&self, _: &str) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::scheduler::process;
+    use super::*;
+    use crate::analyzer::balance::component_units;
+    use rand::SeedableRng;
+
+    const COMPONENT: u16 = 1;
+
+    type AMSink = Arc<Mutex<SimpleSink>>;
+
+    fn sink(code: u16) -> AMSink {
+        Arc::new(Mutex::new(SimpleSink::new(code, COMPONENT)))
+    }
+
+    /// Imports `arrivals[t]` units at each timestep `t`, then lets the actor run until
+    /// `max_time`.
+    fn feed(actor: AMActor, scheduler: &AMScheduler, arrivals: &[u64], max_time: usize) {
+        for time in 0..max_time {
+            if let Some(count) = arrivals.get(time) {
+                let token = Token::new(time as u64, COMPONENT, *count, time);
+                actor
+                    .lock()
+                    .unwrap()
+                    .import(COMPONENT, LinkedList::from([token]), time)
+                    .unwrap();
+            }
+            process(scheduler, time).unwrap();
+        }
+    }
+
+    /// Capacity actor sending to a sink and overflowing into another one.
+    fn capacity_actor(
+        capacity: f64,
+        max_queue: Option<u64>,
+        scheduler: &AMScheduler,
+    ) -> (Arc<Mutex<CapacityActor>>, AMSink, AMSink) {
+        let mut actor = CapacityActor::new(100, COMPONENT, capacity, scheduler.clone());
+        actor.max_queue = max_queue;
+        let (output, overflow) = (sink(200), sink(300));
+        let route = Route::new(TimeSeries::Constant(1.0));
+        actor.register(200, COMPONENT, route.clone(), output.clone());
+        actor.register(300, OVERFLOW, route, overflow.clone());
+        (Arc::new(Mutex::new(actor)), output, overflow)
+    }
+
+    #[test]
+    fn capacity_processes_arrivals_below_capacity() {
+        let scheduler = Scheduler::new();
+        let (actor, output, overflow) = capacity_actor(10.0, None, &scheduler);
+        feed(actor.clone(), &scheduler, &[5, 5, 5, 5], 4);
+        assert_eq!(output.lock().unwrap().total(), 20);
+        assert_eq!(overflow.lock().unwrap().total(), 0);
+        let statistics = actor.lock().unwrap().statistics();
+        assert_eq!(
+            statistics,
+            vec![
+                (0, 0, 0.5, 0),
+                (1, 0, 0.5, 0),
+                (2, 0, 0.5, 0),
+                (3, 0, 0.5, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn capacity_overflows_beyond_capacity_without_queue() {
+        let scheduler = Scheduler::new();
+        let (actor, output, overflow) = capacity_actor(10.0, None, &scheduler);
+        feed(actor.clone(), &scheduler, &[15, 15], 2);
+        assert_eq!(output.lock().unwrap().total(), 20);
+        assert_eq!(overflow.lock().unwrap().total(), 10);
+        let statistics = actor.lock().unwrap().statistics();
+        assert_eq!(statistics, vec![(0, 0, 1.0, 5), (1, 0, 1.0, 5)]);
+    }
+
+    #[test]
+    fn capacity_queues_up_to_max_queue() {
+        let scheduler = Scheduler::new();
+        let (actor, output, overflow) = capacity_actor(10.0, Some(5), &scheduler);
+        feed(actor.clone(), &scheduler, &[15, 15, 0], 3);
+        assert_eq!(output.lock().unwrap().total(), 25);
+        assert_eq!(overflow.lock().unwrap().total(), 5);
+        let statistics = actor.lock().unwrap().statistics();
+        assert_eq!(
+            statistics,
+            vec![(0, 5, 1.0, 0), (1, 5, 1.0, 5), (2, 0, 0.5, 0)]
+        );
+    }
+
+    #[test]
+    fn capacity_queues_everything_without_overflow_route() {
+        let scheduler = Scheduler::new();
+        let actor = Arc::new(Mutex::new(CapacityActor::new(
+            100,
+            COMPONENT,
+            10.0,
+            scheduler.clone(),
+        )));
+        let output = sink(200);
+        actor.lock().unwrap().register(
+            200,
+            COMPONENT,
+            Route::new(TimeSeries::Constant(1.0)),
+            output.clone(),
+        );
+        feed(actor.clone(), &scheduler, &[30], 3);
+        assert_eq!(output.lock().unwrap().total(), 30);
+        let statistics = actor.lock().unwrap().statistics();
+        assert_eq!(
+            statistics,
+            vec![(0, 20, 1.0, 0), (1, 10, 1.0, 0), (2, 0, 1.0, 0)]
+        );
+    }
+
+    /// Broadcast splitting its units between sinks of the given shares.
+    fn broadcast(
+        shares: Vec<TimeSeries>,
+        routing: Routing,
+        scheduler: &AMScheduler,
+    ) -> (Arc<Mutex<Broadcast>>, Vec<AMSink>) {
+        scheduler.lock().unwrap().seed = 42;
+        let actor = Broadcast::new(100, COMPONENT, scheduler.clone());
+        let sinks: Vec<AMSink> = (0..shares.len())
+            .map(|index| sink(200 + index as u16))
+            .collect();
+        for (index, share) in shares.into_iter().enumerate() {
+            actor.lock().unwrap().register(
+                200 + index as u16,
+                COMPONENT,
+                Route::new(share),
+                sinks[index].clone(),
+            );
+        }
+        actor.lock().unwrap().set_routing(routing);
+        (actor, sinks)
+    }
+
+    fn totals(sinks: &[AMSink]) -> Vec<u64> {
+        sinks.iter().map(|s| s.lock().unwrap().total()).collect()
+    }
+
+    fn constants(shares: &[f64]) -> Vec<TimeSeries> {
+        shares.iter().map(|s| TimeSeries::Constant(*s)).collect()
+    }
+
+    #[test]
+    fn integer_weights_keep_integer_shares() {
+        assert_eq!(integer_weights(&[29.0, 7.0, 55.0, 9.0]), vec![29, 7, 55, 9]);
+        assert_eq!(integer_weights(&[0.3, 0.7]), vec![3, 7]);
+        assert_eq!(integer_weights(&[0.07, 0.93]), vec![7, 93]);
+        // Largest remainders get the slots left by the floors, 286, 286 and 428
+        assert_eq!(integer_weights(&[1.0, 1.0, 1.5]), vec![143, 143, 214]);
+    }
+
+    #[test]
+    fn deterministic_routing_follows_weights() {
+        let scheduler = Scheduler::new();
+        let (actor, sinks) = broadcast(
+            constants(&[29.0, 7.0, 55.0, 9.0]),
+            Routing::Deterministic,
+            &scheduler,
+        );
+        feed(actor, &scheduler, &[1000], 1);
+        assert_eq!(totals(&sinks), vec![290, 70, 550, 90]);
+    }
+
+    #[test]
+    fn deterministic_routing_keeps_fractional_shares() {
+        let scheduler = Scheduler::new();
+        let (actor, sinks) =
+            broadcast(constants(&[0.35, 0.65]), Routing::Deterministic, &scheduler);
+        feed(actor, &scheduler, &[100, 100, 100], 3);
+        assert_eq!(totals(&sinks), vec![105, 195]);
+    }
+
+    #[test]
+    fn deterministic_routing_follows_varying_shares() {
+        let scheduler = Scheduler::new();
+        let shares = vec![
+            TimeSeries::Linear(vec![(0, 0.07), (10, 0.30)]),
+            TimeSeries::Linear(vec![(0, 0.93), (10, 0.70)]),
+        ];
+        let (actor, sinks) = broadcast(shares, Routing::Deterministic, &scheduler);
+        // Units only arrive once the shares reached 0.30 and 0.70
+        let mut arrivals = vec![0; 10];
+        arrivals.push(100);
+        feed(actor, &scheduler, &arrivals, 11);
+        assert_eq!(totals(&sinks), vec![30, 70]);
+    }
+
+    #[test]
+    fn routing_fails_without_positive_weight() {
+        for routing in [
+            Routing::Deterministic,
+            Routing::Stochastic,
+            Routing::Multinomial,
+        ] {
+            let scheduler = Scheduler::new();
+            let (actor, _) = broadcast(constants(&[0.0, 0.0]), routing, &scheduler);
+            let token = Token::new(0, COMPONENT, 10, 3);
+            let result = actor
+                .lock()
+                .unwrap()
+                .import(COMPONENT, LinkedList::from([token]), 3);
+            assert_eq!(result, Err(SimulationError::NoClientWeight(100, 3)));
+        }
+    }
+
+    #[test]
+    fn multinomial_routing_conserves_units() {
+        let scheduler = Scheduler::new();
+        let (actor, sinks) = broadcast(
+            constants(&[0.2, 0.0, 0.8]),
+            Routing::Multinomial,
+            &scheduler,
+        );
+        feed(actor, &scheduler, &[10000, 10000], 2);
+        let totals = totals(&sinks);
+        assert_eq!(totals.iter().sum::<u64>(), 20000);
+        assert_eq!(totals[1], 0);
+        assert!((totals[0] as f64 - 4000.0).abs() < 300.0, "{:?}", totals);
+    }
+
+    #[test]
+    fn stochastic_routing_conserves_units() {
+        let scheduler = Scheduler::new();
+        let (actor, sinks) = broadcast(constants(&[1.0, 3.0]), Routing::Stochastic, &scheduler);
+        feed(actor, &scheduler, &[10000], 1);
+        let totals = totals(&sinks);
+        assert_eq!(totals.iter().sum::<u64>(), 10000);
+        assert!((totals[0] as f64 - 2500.0).abs() < 200.0, "{:?}", totals);
+    }
+
+    #[test]
+    fn stochastic_routing_skips_empty_tokens() {
+        let scheduler = Scheduler::new();
+        let (actor, sinks) = broadcast(constants(&[1.0, 3.0]), Routing::Stochastic, &scheduler);
+        feed(actor, &scheduler, &[0, 100], 2);
+        assert_eq!(totals(&sinks).iter().sum::<u64>(), 100);
+    }
+
+    #[test]
+    fn delays_follow_the_residence_pdf() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let pdf = [0.25, 0.0, 0.75];
+        let delays = draw_delays(10000, &pdf, &mut rng);
+        assert_eq!(delays.values().sum::<u64>(), 10000);
+        assert!(!delays.contains_key(&1));
+        assert!((delays[&2] as f64 - 7500.0).abs() < 200.0, "{:?}", delays);
+        // Fewer units than delays are drawn one by one
+        let delays = draw_delays(2, &pdf, &mut rng);
+        assert_eq!(delays.values().sum::<u64>(), 2);
+        assert!(!delays.contains_key(&1));
+    }
+
+    #[test]
+    fn multinomial_splits_all_units() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let counts = multinomial(1000, &[0.5, 0.0, 0.25, 0.25], &mut rng);
+        assert_eq!(counts.iter().sum::<u64>(), 1000);
+        assert_eq!(counts[1], 0);
+        assert_eq!(multinomial(1000, &[0.0, 1.0], &mut rng), vec![0, 1000]);
+    }
+
+    #[test]
+    fn simple_actor_rejects_components_it_does_not_handle() {
+        let scheduler = Scheduler::new();
+        let mut actor = SimpleActor::new(100, &[COMPONENT, 2], scheduler);
+        assert!(actor.accepts(2));
+        assert!(!actor.accepts(3));
+        let tokens = LinkedList::from([Token::new(0, 2, 10, 0), Token::new(1, 3, 10, 0)]);
+        assert_eq!(
+            actor.import(2, tokens, 0),
+            Err(SimulationError::UnexpectedComponent(100, 3))
+        );
+        assert_eq!(actor.total(), 0);
+    }
+
+    #[test]
+    fn assembly_rejects_components_outside_its_recipe() {
+        let scheduler = Scheduler::new();
+        let recipe = HashMap::from([(COMPONENT, 2)]);
+        let mut actor = AssemblyActor::new(100, 3, recipe, scheduler);
+        let token = Token::new(0, 2, 10, 0);
+        assert_eq!(
+            actor.import(2, LinkedList::from([token]), 0),
+            Err(SimulationError::UnexpectedComponent(100, 2))
+        );
+        assert_eq!(actor.total(), 0);
+    }
+
+    #[test]
+    fn assembly_builds_products_in_batches() {
+        let scheduler = Scheduler::new();
+        let recipe = HashMap::from([(1, 2), (2, 3)]);
+        let mut actor = AssemblyActor::new(100, 3, recipe, scheduler.clone());
+        let output = Arc::new(Mutex::new(SimpleSink::new(200, 3)));
+        let route = Route::new(TimeSeries::Constant(1.0));
+        actor.register(200, 3, route, output.clone());
+        let arrivals = [
+            Token::new(0, 1, 4, 0),
+            Token::new(1, 1, 6, 0),
+            Token::new(2, 2, 15, 0),
+        ];
+        for token in arrivals {
+            let code = token.code;
+            actor.import(code, LinkedList::from([token]), 0).unwrap();
+        }
+        process(&scheduler, 0).unwrap();
+        assert_eq!(actor.created(), vec![(3, 5)]);
+        // One batch per cohort of the first component
+        let products = output.lock().unwrap().tokens();
+        let batches: Vec<u64> = products.iter().map(|p| p.count).collect();
+        assert_eq!(batches, vec![2, 3]);
+        for product in products.iter() {
+            let parts: Vec<(u16, u64)> = product
+                .parts
+                .iter()
+                .map(|(code, parts)| (*code, units(parts)))
+                .collect();
+            assert_eq!(parts, vec![(1, 2), (2, 3)]);
+        }
+        let units = component_units(&products);
+        assert_eq!(units, BTreeMap::from([(1, 10), (2, 15), (3, 5)]));
+    }
+
+    #[test]
+    fn disassembly_carries_fractional_recoveries() {
+        let scheduler = Scheduler::new();
+        let recovery = HashMap::from([(1, 0.5)]);
+        let mut actor = DisassemblyActor::new(100, 3, recovery, scheduler.clone());
+        let (recovered, residue) = (
+            Arc::new(Mutex::new(SimpleSink::new(200, 1))),
+            Arc::new(Mutex::new(SimpleSink::new(300, 1))),
+        );
+        let route = Route::new(TimeSeries::Constant(1.0));
+        actor.register(200, 1, route.clone(), recovered.clone());
+        actor.register(300, RESIDUE, route, residue.clone());
+        assert_eq!(actor.outputs(), vec![RESIDUE]);
+        for time in 0..2 {
+            let mut product = Token::new(time as u64, 3, 1, time);
+            product.add_part(1, LinkedList::from([Token::new(10 + time as u64, 1, 3, 0)]));
+            actor.import(3, LinkedList::from([product]), time).unwrap();
+            process(&scheduler, time).unwrap();
+        }
+        // 1.5 parts are recovered from each product, the half carried to the next one
+        assert_eq!(recovered.lock().unwrap().total(), 3);
+        let residues = component_units(&residue.lock().unwrap().tokens());
+        assert_eq!(residues, BTreeMap::from([(1, 3), (3, 2)]));
+        let token = Token::new(2, 1, 1, 2);
+        assert_eq!(
+            actor.import(1, LinkedList::from([token]), 2),
+            Err(SimulationError::UnexpectedComponent(100, 1))
+        );
+    }
+
+    #[test]
+    fn losses_are_sent_to_the_compartments() {
+        let scheduler = Scheduler::new();
+        let mut actor = LossActor::new(100, COMPONENT, scheduler.clone());
+        let output = sink(200);
+        actor.register(
+            200,
+            COMPONENT,
+            Route::new(TimeSeries::Constant(1.0)),
+            output.clone(),
+        );
+        let air = sink(300);
+        let water = sink(400);
+        actor.register(
+            300,
+            LOSS,
+            Route::new(TimeSeries::Constant(0.1)),
+            air.clone(),
+        );
+        actor.register(
+            400,
+            LOSS,
+            Route::new(TimeSeries::Constant(0.15)),
+            water.clone(),
+        );
+        feed(Arc::new(Mutex::new(actor)), &scheduler, &[30, 40], 2);
+        assert_eq!(air.lock().unwrap().total(), 7);
+        assert_eq!(water.lock().unwrap().total(), 10);
+        assert_eq!(output.lock().unwrap().total(), 53);
+        let scheduler = scheduler.lock().unwrap();
+        let lost: u64 = scheduler.flows[&(100, 300, COMPONENT)].values().sum();
+        assert_eq!(lost, 7);
+    }
+
+    #[test]
+    fn transform_only_converts_its_input() {
+        let scheduler = Scheduler::new();
+        let mut actor = TransformActor::new(100, COMPONENT, 3, (2, 1), 0, scheduler);
+        let token = Token::new(0, 2, 10, 0);
+        assert_eq!(
+            actor.import(2, LinkedList::from([token]), 0),
+            Err(SimulationError::UnexpectedComponent(100, 2))
+        );
+        let token = Token::new(1, COMPONENT, 10, 0);
+        actor
+            .import(COMPONENT, LinkedList::from([token]), 0)
+            .unwrap();
+        assert_eq!(actor.created(), vec![(3, 5)]);
+    }
+
+    #[test]
+    fn transform_merges_consumed_tokens_of_the_same_history() {
+        let scheduler = Scheduler::new();
+        let mut actor = TransformActor::new(100, COMPONENT, 3, (2, 1), 0, scheduler);
+        for id in 0..3 {
+            let mut token = Token::new(id, COMPONENT, 4, 0);
+            token.age(50, 0);
+            actor
+                .import(COMPONENT, LinkedList::from([token]), 1)
+                .unwrap();
+        }
+        let mut token = Token::new(3, COMPONENT, 4, 0);
+        token.age(60, 0);
+        actor
+            .import(COMPONENT, LinkedList::from([token]), 1)
+            .unwrap();
+        let consumed: Vec<u64> = actor.consumed.iter().map(|t| t.count).collect();
+        assert_eq!(consumed, vec![12, 4]);
+    }
+
+    /// Source of `quantity` units every `period` timesteps sending them to a sink.
+    fn source(
+        quantity: f64,
+        period: usize,
+        noise: Option<SupplyNoise>,
+        scheduler: &AMScheduler,
+    ) -> (SimpleSource, AMSink) {
+        scheduler.lock().unwrap().seed = 7;
+        let mut source = SimpleSource::new(
+            100,
+            COMPONENT,
+            TimeSeries::Constant(quantity),
+            period,
+            u64::MAX,
+            scheduler.clone(),
+        );
+        source.noise = noise;
+        let output = sink(200);
+        let route = Route::new(TimeSeries::Constant(1.0));
+        source.register(200, COMPONENT, route, output.clone());
+        (source, output)
+    }
+
+    /// Units supplied at each timestep until `max_time`, or until the source stops.
+    fn supplied(source: &mut SimpleSource, scheduler: &AMScheduler, max_time: usize) -> Vec<u64> {
+        let mut supplied = vec![];
+        for time in 0..max_time {
+            let before = source.total;
+            if !source.supply(time).unwrap() {
+                break;
+            }
+            process(scheduler, time).unwrap();
+            supplied.push(source.total - before);
+        }
+        supplied
+    }
+
+    #[test]
+    fn source_follows_its_schedule() {
+        let scheduler = Scheduler::new();
+        let (mut source, output) = source(10.0, 3, None, &scheduler);
+        source.start = 2;
+        source.end = Some(10);
+        assert_eq!(
+            supplied(&mut source, &scheduler, 20),
+            vec![0, 0, 10, 0, 0, 10, 0, 0, 10, 0]
+        );
+        assert_eq!(output.lock().unwrap().total(), 30);
+    }
+
+    #[test]
+    fn source_stops_at_max_production() {
+        let scheduler = Scheduler::new();
+        let (mut source, _) = source(10.0, 1, None, &scheduler);
+        source.max_production = 25;
+        assert_eq!(supplied(&mut source, &scheduler, 10), vec![10, 10, 5]);
+    }
+
+    /// Mean and standard deviation of the quantities supplied over `draws` timesteps.
+    fn noise_moments(noise: SupplyNoise, draws: usize) -> (f64, f64) {
+        let scheduler = Scheduler::new();
+        let (mut source, _) = source(100.0, 1, Some(noise), &scheduler);
+        let supplied = supplied(&mut source, &scheduler, draws);
+        let n = supplied.len() as f64;
+        let mean = supplied.iter().sum::<u64>() as f64 / n;
+        let var = supplied
+            .iter()
+            .map(|q| (*q as f64 - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0);
+        (mean, var.sqrt())
+    }
+
+    #[test]
+    fn poisson_noise() {
+        let (mean, std) = noise_moments(SupplyNoise::Poisson, 5000);
+        assert!((mean - 100.0).abs() < 1.0, "{}", mean);
+        assert!((std - 10.0).abs() < 0.5, "{}", std);
+    }
+
+    #[test]
+    fn normal_noise() {
+        let (mean, std) = noise_moments(SupplyNoise::Normal(0.2), 5000);
+        assert!((mean - 100.0).abs() < 1.0, "{}", mean);
+        assert!((std - 20.0).abs() < 1.0, "{}", std);
+    }
+
+    #[test]
+    fn noise_rejects_invalid_cv() {
+        for doc in [
+            "{normal: {cv: -0.1}}",
+            "{normal: {cv: .nan}}",
+            "{normal: {cv: .inf}}",
+        ] {
+            let doc = &yaml_rust2::YamlLoader::load_from_str(doc).unwrap()[0];
+            assert!(SupplyNoise::parse(doc).is_err(), "{:?}", doc);
+        }
+    }
+
+    #[test]
+    fn noise_fails_on_an_infinite_quantity() {
+        let scheduler = Scheduler::new();
+        let (mut source, _) = source(f64::INFINITY, 1, Some(SupplyNoise::Poisson), &scheduler);
+        assert_eq!(
+            source.supply(0),
+            Err(SimulationError::InvalidSupply(100, 0))
+        );
+    }
+
+    /// Units supplied at each timestep by a [TimeSeriesSource] reading `rows`, with
+    /// half-year timesteps and the series starting in 2001.
+    fn series_supplied(rows: &str, interpolation: &str) -> Vec<u64> {
+        let path = std::env::temp_dir().join(format!("series_{}.csv", interpolation));
+        fs::write(&path, format!("year,quantity\n{}", rows)).unwrap();
+        let doc = format!(
+            "{{component: c, file: {}, origin: 2001, interpolation: {}}}",
+            path.display(),
+            interpolation
+        );
+        let doc = &yaml_rust2::YamlLoader::load_from_str(&doc).unwrap()[0];
+        let scheduler = Scheduler::new();
+        scheduler.lock().unwrap().dt = 0.5;
+        let components = HashMap::from([(String::from("c"), COMPONENT)]);
+        let source = TimeSeriesSource::parse(doc, 100, components, scheduler).unwrap();
+        let mut source = source.lock().unwrap();
+        let mut supplied = vec![];
+        for time in 0..10 {
+            let before = source.total();
+            if !source.as_source().supply(time).unwrap() {
+                break;
+            }
+            supplied.push(source.total() - before);
+        }
+        supplied
+    }
+
+    #[test]
+    fn series_source_starts_at_its_origin() {
+        // The row of 2000 is superseded by the one of 2001, and 2002 lasts a year
+        let rows = "2000,10\n2001,20\n2002,40\n";
+        assert_eq!(series_supplied(rows, "step"), vec![10, 10, 20, 20]);
+        assert_eq!(series_supplied(rows, "linear"), vec![10, 15, 20, 20]);
+    }
+
+    /// Timesteps at which a [SimpleActor] holding for 5 timesteps releases an initial
+    /// stock of 10 units aged `age` timesteps, with or without known distributions.
+    fn stock_releases(age: usize, known: bool) -> BTreeMap<usize, u64> {
+        let scheduler = Scheduler::new();
+        let constant = |value: usize| {
+            let sampler: Sampler = Arc::new(move |_: &mut ChaCha8Rng| value);
+            let cdf: Cdf = Arc::new(move |t: usize| if t >= value { 1.0 } else { 0.0 });
+            (sampler, Some(cdf).filter(|_| known))
+        };
+        let mut actor = SimpleActor::new(100, &[COMPONENT], scheduler.clone());
+        let (residence, residence_cdf) = constant(5);
+        actor.set_residence_time(COMPONENT, residence, residence_cdf);
+        let (age, age_cdf) = constant(age);
+        actor.initial_stock = Some(InitialStock {
+            quantity: 10,
+            age,
+            age_cdf,
+        });
+        let output = sink(200);
+        let route = Route::new(TimeSeries::Constant(1.0));
+        actor.register(200, COMPONENT, route, output.clone());
+        actor.supply(0).unwrap();
+        let mut releases = BTreeMap::new();
+        for time in 0..10 {
+            let before = output.lock().unwrap().total();
+            process(&scheduler, time).unwrap();
+            let received = output.lock().unwrap().total() - before;
+            if received > 0 {
+                releases.insert(time, received);
+            }
+        }
+        releases
+    }
+
+    #[test]
+    fn initial_stock_leaves_after_its_remaining_residence_time() {
+        for known in [true, false] {
+            assert_eq!(stock_releases(3, known), BTreeMap::from([(2, 10)]));
+            // Units older than any residence time leave at once
+            assert_eq!(stock_releases(7, known), BTreeMap::from([(0, 10)]));
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use std::collections::LinkedList;
 
-use super::tokens::Token;
+use super::tokens::{take_units, Token};
 
 #[derive(Clone)]
 pub struct Fifo {
     pub code: u16,
     pub tokens: LinkedList<Token>,
     pub log: bool,
+    available: u64,
 }
 
 impl Fifo {
@@ -15,39 +16,44 @@ impl Fifo {
             code,
             tokens: LinkedList::new(),
             log,
+            available: 0,
         }
     }
 
-    pub fn available_tokens(&self) -> u32 {
-        self.tokens.len() as u32
+    /// Number of units stored, whatever the number of cohorts.
+    pub fn available_tokens(&self) -> u64 {
+        self.available
     }
 
-    pub fn put(&mut self, mut new_tokens: LinkedList<Token>) {
+    pub fn put(&mut self, mut new_tokens: LinkedList<Token>, time: usize) {
         if new_tokens.is_empty() {
             return;
         }
-        if self.log {
-            for t in new_tokens.iter_mut() {
-                t.age(self.code)
+        for t in new_tokens.iter_mut() {
+            if self.log {
+                t.age(self.code, time)
             }
+            self.available += t.count;
         }
         new_tokens.append(&mut self.tokens);
         self.tokens = new_tokens;
     }
 
-    pub fn get(&mut self, quantity: u32) -> LinkedList<Token> {
+    pub fn get(&mut self, quantity: u64) -> LinkedList<Token> {
         if quantity == 0 {
             return LinkedList::new();
         }
-        self.tokens
-            .split_off(self.tokens.len() - (quantity as usize))
+        self.available -= quantity;
+        take_units(&mut self.tokens, quantity)
     }
 
     pub fn get_all(&mut self) -> LinkedList<Token> {
+        self.available = 0;
         self.tokens.split_off(0)
     }
 
     pub fn reset(&mut self) {
         self.tokens = LinkedList::new();
+        self.available = 0;
     }
 }
@@ -1,3 +1,6 @@
 pub mod actor;
 pub mod fifo;
+pub mod route;
+pub mod scheduler;
+pub mod time_series;
 pub mod tokens;
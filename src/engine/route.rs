@@ -0,0 +1,105 @@
+use super::time_series::TimeSeries;
+use super::tokens::Token;
+
+/// Property of a [Token] a [Condition] can compare.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attribute {
+    /// Number of entries in the actor of the given code
+    Visits(u16),
+    /// Timesteps since the creation of the token
+    Age,
+    Quality,
+    /// Code of the component
+    Component,
+}
+
+impl Attribute {
+    fn value(&self, token: &Token, time: usize) -> f64 {
+        match self {
+            Attribute::Visits(actor) => token.visits(*actor) as f64,
+            Attribute::Age => time.saturating_sub(token.created) as f64,
+            Attribute::Quality => token.quality,
+            Attribute::Component => token.code as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    fn compare(&self, a: f64, b: f64) -> bool {
+        match self {
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+        }
+    }
+}
+
+/// Predicate on the properties of a [Token], such as `visits(recycling) >= 3`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Compare(Attribute, Comparison, f64),
+    /// The token embeds a part of the given component, at any depth
+    Contains(u16),
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn matches(&self, token: &Token, time: usize) -> bool {
+        match self {
+            Condition::Compare(attribute, comparison, value) => {
+                comparison.compare(attribute.value(token, time), *value)
+            }
+            Condition::Contains(code) => contains(token, *code),
+            Condition::Not(condition) => !condition.matches(token, time),
+            Condition::And(conditions) => conditions.iter().all(|c| c.matches(token, time)),
+            Condition::Or(conditions) => conditions.iter().any(|c| c.matches(token, time)),
+        }
+    }
+}
+
+fn contains(token: &Token, code: u16) -> bool {
+    token.parts.iter().any(|(part, tokens)| {
+        (*part == code && !tokens.is_empty()) || tokens.iter().any(|t| contains(t, code))
+    })
+}
+
+/// Link between an actor and one of its clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    /// Weight of the client in the split of the tokens matching no condition, clients
+    /// with a condition taking no part in it
+    pub share: TimeSeries,
+    /// Tokens matching the condition are all sent to the client, no condition meaning
+    /// the client only takes part in the weighted split
+    pub condition: Option<Condition>,
+}
+
+impl Route {
+    pub fn new(share: TimeSeries) -> Route {
+        Route {
+            share,
+            condition: None,
+        }
+    }
+
+    pub fn matches(&self, token: &Token, time: usize) -> bool {
+        self.condition
+            .as_ref()
+            .is_some_and(|condition| condition.matches(token, time))
+    }
+}
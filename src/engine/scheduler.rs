@@ -0,0 +1,252 @@
+use rand::{thread_rng, Rng};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, LinkedList};
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use super::actor::AMActor;
+use super::tokens::Token;
+
+pub type AMScheduler = Arc<Mutex<Scheduler>>;
+pub type SimulationResult<T> = std::result::Result<T, SimulationError>;
+
+/// Problem met by an actor during the simulation, which stops it. Actors and components
+/// are given by their codes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationError {
+    /// Actor that received units of a component it does not accept
+    UnexpectedComponent(u16, u16),
+    /// Actor whose clients all have a zero weight at the given timestep
+    NoClientWeight(u16, usize),
+    /// Source whose supply distribution cannot be drawn from at the given timestep
+    InvalidSupply(u16, usize),
+}
+
+impl SimulationError {
+    /// Message of the error, given the labels of the actor and component codes.
+    pub fn describe(
+        &self,
+        actors: &HashMap<u16, String>,
+        components: &HashMap<u16, String>,
+    ) -> String {
+        let label = |labels: &HashMap<u16, String>, code: &u16| {
+            labels
+                .get(code)
+                .cloned()
+                .unwrap_or_else(|| code.to_string())
+        };
+        match self {
+            SimulationError::UnexpectedComponent(actor, component) => format!(
+                "Actor {} received {}, which it does not accept",
+                label(actors, actor),
+                label(components, component)
+            ),
+            SimulationError::NoClientWeight(actor, time) => format!(
+                "Actor {} has no client with a positive weight at timestep {}",
+                label(actors, actor),
+                time
+            ),
+            SimulationError::InvalidSupply(actor, time) => format!(
+                "Source {} cannot draw its supply at timestep {}",
+                label(actors, actor),
+                time
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe(&HashMap::new(), &HashMap::new()))
+    }
+}
+
+/// Delivery of [Tokens][Token] to an actor at a given timestep.
+pub struct Event {
+    pub time: usize,
+    order: u64,
+    pub target: AMActor,
+    pub code_product: u16,
+    pub tokens: LinkedList<Token>,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.order) == (other.time, other.order)
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    /// Events are popped earliest first, and in insertion order for a same timestep.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.time, other.order).cmp(&(self.time, self.order))
+    }
+}
+
+/// Event queue of the simulation. Actors never call each other directly: they schedule
+/// the delivery of their tokens, possibly in the future when they hold them for a
+/// residence time.
+pub struct Scheduler {
+    events: BinaryHeap<Event>,
+    order: u64,
+    /// Seed of the random streams of the tokens
+    pub seed: u64,
+    /// Duration of a timestep, used by actors to convert times into timesteps
+    pub dt: f64,
+    next_id: u64,
+    /// Units sent per timestep along each `(actor, client, component)` route
+    pub flows: BTreeMap<(u16, u16, u16), BTreeMap<usize, u64>>,
+}
+
+impl Scheduler {
+    pub fn new() -> AMScheduler {
+        Arc::new(Mutex::new(Scheduler {
+            events: BinaryHeap::new(),
+            order: 0,
+            seed: thread_rng().gen(),
+            dt: 1.0,
+            next_id: 0,
+            flows: BTreeMap::new(),
+        }))
+    }
+
+    /// Reserves `quantity` identifiers for new tokens.
+    pub fn new_ids(&mut self, quantity: usize) -> Range<u64> {
+        let first = self.next_id;
+        self.next_id += quantity as u64;
+        first..self.next_id
+    }
+
+    /// Records `units` of the component `component` sent by the actor `from` to its
+    /// client `to` at `time`.
+    pub fn record_flow(&mut self, from: u16, to: u16, component: u16, time: usize, units: u64) {
+        if units == 0 {
+            return;
+        }
+        *self
+            .flows
+            .entry((from, to, component))
+            .or_default()
+            .entry(time)
+            .or_default() += units;
+    }
+
+    pub fn schedule(
+        &mut self,
+        time: usize,
+        target: AMActor,
+        code_product: u16,
+        tokens: LinkedList<Token>,
+    ) {
+        if tokens.is_empty() {
+            return;
+        }
+        self.events.push(Event {
+            time,
+            order: self.order,
+            target,
+            code_product,
+            tokens,
+        });
+        self.order += 1;
+    }
+
+    /// Pops the next event happening at or before `time`.
+    pub fn next(&mut self, time: usize) -> Option<Event> {
+        match self.events.peek() {
+            Some(event) if event.time <= time => self.events.pop(),
+            _ => None,
+        }
+    }
+
+    /// Events that were not processed yet.
+    pub fn pending(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    /// Removes the tokens of the events that were not processed yet.
+    pub fn in_flight(&mut self) -> LinkedList<Token> {
+        let mut tokens = LinkedList::new();
+        for mut event in self.events.drain() {
+            tokens.append(&mut event.tokens);
+        }
+        tokens
+    }
+
+    pub fn reset(&mut self) {
+        self.events.clear();
+        self.order = 0;
+        self.next_id = 0;
+        self.flows.clear();
+    }
+}
+
+/// Delivers every event scheduled up to `time`, including the ones created meanwhile.
+pub fn process(scheduler: &AMScheduler, time: usize) -> SimulationResult<()> {
+    loop {
+        // The scheduler must be released before delivering, as actors schedule new events
+        let event = scheduler.lock().unwrap().next(time);
+        match event {
+            Some(event) => {
+                event
+                    .target
+                    .lock()
+                    .unwrap()
+                    .import(event.code_product, event.tokens, event.time)?
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Runs the simulation from timestep 0 to `max_time`. Sources supply once per timestep
+/// until they are exhausted. The simulation stops at the first error of an actor.
+pub fn run(scheduler: &AMScheduler, sources: &[AMActor], max_time: usize) -> SimulationResult<()> {
+    let mut sources = sources.to_vec();
+    for time in 0..max_time {
+        let mut active = Vec::with_capacity(sources.len());
+        for source in sources {
+            if source.lock().unwrap().as_source().supply(time)? {
+                active.push(source);
+            }
+        }
+        sources = active;
+        process(scheduler, time)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::actor::SimpleSink;
+
+    #[test]
+    fn events_are_popped_by_time_then_insertion_order() {
+        let scheduler = Scheduler::new();
+        let sink: AMActor = Arc::new(Mutex::new(SimpleSink::new(100, 1)));
+        let mut scheduler = scheduler.lock().unwrap();
+        for (id, time) in [(0, 3), (1, 1), (2, 2), (3, 1)] {
+            let token = Token::new(id, 1, 1, 0);
+            scheduler.schedule(time, sink.clone(), 1, LinkedList::from([token]));
+        }
+        // Empty deliveries are not scheduled
+        scheduler.schedule(0, sink.clone(), 1, LinkedList::new());
+        assert!(scheduler.next(0).is_none());
+        let mut popped = vec![];
+        while let Some(event) = scheduler.next(2) {
+            popped.push((event.time, event.tokens.front().unwrap().id));
+        }
+        assert_eq!(popped, vec![(1, 1), (1, 3), (2, 2)]);
+        assert_eq!(scheduler.pending().count(), 1);
+    }
+}
@@ -0,0 +1,78 @@
+/// Value varying over the simulated time, such as the weight of a client of a
+/// [Broadcast][super::actor::Broadcast] or the output of a source. Points are
+/// `(timestep, value)` pairs sorted by timestep.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeSeries {
+    Constant(f64),
+    /// Piecewise-linear interpolation between the points, constant before the first one
+    /// and after the last one
+    Linear(Vec<(usize, f64)>),
+    /// Value of the last point reached, the first value before it
+    Step(Vec<(usize, f64)>),
+}
+
+impl TimeSeries {
+    /// Value at `time`.
+    pub fn at(&self, time: usize) -> f64 {
+        match self {
+            TimeSeries::Constant(weight) => *weight,
+            TimeSeries::Step(points) => {
+                let index = points.partition_point(|(t, _)| *t <= time);
+                points[index.saturating_sub(1)].1
+            }
+            TimeSeries::Linear(points) => {
+                let index = points.partition_point(|(t, _)| *t <= time);
+                if index == 0 {
+                    return points[0].1;
+                }
+                if index == points.len() {
+                    return points[index - 1].1;
+                }
+                let ((t0, w0), (t1, w1)) = (points[index - 1], points[index]);
+                w0 + (w1 - w0) * (time - t0) as f64 / (t1 - t0) as f64
+            }
+        }
+    }
+
+    /// Whether the value is never positive, interpolations staying between their points.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            TimeSeries::Constant(value) => *value <= 0.0,
+            TimeSeries::Linear(points) | TimeSeries::Step(points) => {
+                points.iter().all(|(_, value)| *value <= 0.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_interpolates_between_points() {
+        let series = TimeSeries::Linear(vec![(10, 1.0), (20, 3.0), (30, 0.0)]);
+        let values: Vec<f64> = [0, 10, 15, 20, 25, 30, 40]
+            .iter()
+            .map(|time| series.at(*time))
+            .collect();
+        assert_eq!(values, vec![1.0, 1.0, 2.0, 3.0, 1.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn step_keeps_the_last_point_reached() {
+        let series = TimeSeries::Step(vec![(10, 1.0), (20, 3.0)]);
+        let values: Vec<f64> = [0, 10, 19, 20, 100]
+            .iter()
+            .map(|time| series.at(*time))
+            .collect();
+        assert_eq!(values, vec![1.0, 1.0, 1.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn zero_series() {
+        assert!(TimeSeries::Constant(0.0).is_zero());
+        assert!(TimeSeries::Linear(vec![(0, 0.0), (10, -1.0)]).is_zero());
+        assert!(!TimeSeries::Step(vec![(0, 0.0), (10, 2.0)]).is_zero());
+    }
+}
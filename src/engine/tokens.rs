@@ -1,25 +1,86 @@
-use std::collections::{HashMap, LinkedList};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::{BTreeMap, LinkedList};
 
+/// Visit of a [Token] in an actor. `code` is the sum of the actor and component codes.
+/// The exit is unknown while the actor did not decide when to release the token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Stamp {
+    pub code: u16,
+    pub entry: usize,
+    pub exit: Option<usize>,
+}
+
+/// Cohort of `count` identical units of a component moving together through the graph.
+/// Cohorts are only split when units have to follow different paths.
 #[derive(Debug, Clone)]
 pub struct Token {
+    /// Identifier of the random stream of the token
+    pub id: u64,
     pub code: u16,
-    pub timeline: LinkedList<u16>,
-    pub parts: HashMap<u16, LinkedList<Token>>,
+    pub count: u64,
+    /// Timestep at which the token was created
+    pub created: usize,
+    pub timeline: Vec<Stamp>,
+    /// Parts embedded in each unit of the cohort
+    pub parts: BTreeMap<u16, LinkedList<Token>>,
+    /// Identifiers of the tokens consumed to produce this one
+    pub origins: Vec<u64>,
+    /// Quality grade, starting at 1 and lowered by degrading actors
+    pub quality: f64,
+    draws: u64,
+}
+
+/// Mixes `value` into a well distributed identifier (SplitMix64 finalizer).
+fn mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9E3779B97F4A7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D049BB133111EB);
+    value ^ (value >> 31)
 }
 
 impl Token {
-    pub fn new(code: u16, init: Option<u16>) -> Token {
-        let mut timeline = LinkedList::new();
-        if let Some(code) = init {
-            timeline.push_back(code);
-        }
+    pub fn new(id: u64, code: u16, count: u64, created: usize) -> Token {
         Token {
+            id,
             code,
-            timeline,
-            parts: HashMap::new(),
+            count,
+            created,
+            timeline: vec![],
+            parts: BTreeMap::new(),
+            origins: vec![],
+            quality: 1.0,
+            draws: 0,
         }
     }
 
+    /// Returns a random generator for the next draw of this token. Draws only depend on
+    /// the seed, the token identifier and the number of previous draws, so results do
+    /// not depend on the order in which tokens are processed.
+    pub fn rng(&mut self, seed: u64) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(self.id);
+        rng.set_word_pos((self.draws as u128) << 32);
+        self.draws += 1;
+        rng
+    }
+
+    /// Detaches `count` units from the cohort into a new cohort with its own random
+    /// stream.
+    pub fn split(&mut self, count: u64) -> Token {
+        assert!(
+            count <= self.count,
+            "Cannot split more units than the cohort has"
+        );
+        self.count -= count;
+        let mut token = self.clone();
+        token.count = count;
+        token.id = mix(self.id ^ mix(self.draws));
+        token.draws = 0;
+        self.draws += 1;
+        token
+    }
+
     pub fn add_part(&mut self, code: u16, mut tokens: LinkedList<Token>) {
         if let Some(l) = self.parts.get_mut(&code) {
             l.append(&mut tokens);
@@ -28,12 +89,75 @@ impl Token {
         }
     }
 
-    pub fn age(&mut self, code: u16) {
-        self.timeline.push_back(code + self.code);
+    /// Returns this token followed by all the parts it embeds, recursively, along with
+    /// the number of units each of them represents.
+    pub fn flatten(&self) -> Vec<(&Token, u64)> {
+        let mut res = vec![(self, self.count)];
+        for t in self.parts.values().flatten() {
+            res.extend(
+                t.flatten()
+                    .into_iter()
+                    .map(|(part, count)| (part, count * self.count)),
+            );
+        }
+        res
+    }
+
+    /// Number of times the token entered the actor of code `actor`.
+    pub fn visits(&self, actor: u16) -> usize {
+        self.timeline
+            .iter()
+            .filter(|stamp| stamp.code == actor + self.code)
+            .count()
+    }
+
+    /// Records the entry of the token, and of its parts, in actor `code` at `time`.
+    pub fn age(&mut self, code: u16, time: usize) {
+        self.timeline.push(Stamp {
+            code: code + self.code,
+            entry: time,
+            exit: None,
+        });
+        for (_, tokens) in self.parts.iter_mut() {
+            for t in tokens {
+                t.age(code, time);
+            }
+        }
+    }
+
+    /// Records the exit of the token, and of its parts, from the actor it last entered.
+    pub fn leave(&mut self, time: usize) {
+        if let Some(stamp) = self.timeline.last_mut() {
+            stamp.exit = Some(time);
+        }
         for (_, tokens) in self.parts.iter_mut() {
             for t in tokens {
-                t.age(code);
+                t.leave(time);
             }
         }
     }
 }
+
+/// Number of units in a list of cohorts.
+pub fn units(tokens: &LinkedList<Token>) -> u64 {
+    tokens.iter().map(|t| t.count).sum()
+}
+
+/// Removes `quantity` units from the end of `tokens`, splitting a cohort if needed.
+pub fn take_units(tokens: &mut LinkedList<Token>, quantity: u64) -> LinkedList<Token> {
+    let mut res = LinkedList::new();
+    let mut remaining = quantity;
+    while remaining > 0 {
+        let mut token = tokens
+            .pop_back()
+            .expect("Not enough units to take from the cohorts");
+        if token.count > remaining {
+            res.push_front(token.split(remaining));
+            tokens.push_back(token);
+            break;
+        }
+        remaining -= token.count;
+        res.push_front(token);
+    }
+    res
+}
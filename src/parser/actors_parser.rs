@@ -3,20 +3,23 @@ use std::{
     sync::{Arc, LazyLock, Mutex},
 };
 
-use threadpool::ThreadPool;
 use yaml_rust2::Yaml;
 
-use crate::engine::actor::{AMActor, Actor, SimpleActor, SimpleSink, SimpleSource};
+use crate::engine::actor::{
+    AMActor, Actor, AssemblyActor, CapacityActor, DisassemblyActor, LossActor, SimpleActor,
+    SimpleSink, SimpleSource, TimeSeriesSource, TransformActor,
+};
+use crate::engine::scheduler::AMScheduler;
 
 use super::yaml_parser::Result;
 
-type ActorCallback = fn(&Yaml, u16, HashMap<String, u16>, ThreadPool) -> Result<AMActor>;
+type ActorCallback = fn(&Yaml, u16, HashMap<String, u16>, AMScheduler) -> Result<AMActor>;
 pub static ACTORS: LazyLock<Arc<Mutex<HashMap<String, ActorCallback>>>> =
     LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
 
 pub fn add_actor_implementation(
     label: String,
-    callback: fn(&Yaml, u16, HashMap<String, u16>, ThreadPool) -> Result<AMActor>,
+    callback: fn(&Yaml, u16, HashMap<String, u16>, AMScheduler) -> Result<AMActor>,
 ) {
     ACTORS.lock().unwrap().insert(label, callback);
 }
@@ -25,4 +28,10 @@ pub fn import_default_actors() {
     add_actor_implementation(String::from("SimpleActor"), SimpleActor::parse);
     add_actor_implementation(String::from("SimpleSink"), SimpleSink::parse);
     add_actor_implementation(String::from("SimpleSource"), SimpleSource::parse);
+    add_actor_implementation(String::from("AssemblyActor"), AssemblyActor::parse);
+    add_actor_implementation(String::from("DisassemblyActor"), DisassemblyActor::parse);
+    add_actor_implementation(String::from("TransformActor"), TransformActor::parse);
+    add_actor_implementation(String::from("TimeSeriesSource"), TimeSeriesSource::parse);
+    add_actor_implementation(String::from("CapacityActor"), CapacityActor::parse);
+    add_actor_implementation(String::from("LossActor"), LossActor::parse);
 }
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use yaml_rust2::Yaml;
+
+use crate::engine::actor::AMActor;
+use crate::engine::route::{Attribute, Comparison, Condition};
+
+use super::yaml_parser::{ParseError, Result, YamlParser};
+
+/// Labels and timestep duration needed to resolve the names used in a condition.
+pub struct ConditionContext<'a> {
+    pub actors: &'a HashMap<String, AMActor>,
+    pub components: &'a HashMap<String, u16>,
+    pub dt: f64,
+}
+
+impl ConditionContext<'_> {
+    fn actor(&self, label: &str) -> Result<u16> {
+        let actor = self
+            .actors
+            .get(label)
+            .ok_or_else(|| ParseError::UnknownActor(String::from(label)))?;
+        Ok(actor.lock().unwrap().code())
+    }
+
+    fn component(&self, label: &str) -> Result<u16> {
+        self.components
+            .get(label)
+            .copied()
+            .ok_or_else(|| ParseError::UnknownComponent(String::from(label)))
+    }
+}
+
+/// Parses the `when` condition of a client. It is either an expression such as
+/// `visits(recycling) >= 3 and not contains(cap)`, or a hash of `min_visits` and
+/// `max_quality` bounds.
+pub fn parse_condition(doc: &Yaml, context: &ConditionContext) -> Result<Condition> {
+    match doc {
+        Yaml::String(expression) => {
+            let mut parser = ExpressionParser {
+                tokens: tokenize(expression)?,
+                position: 0,
+                context,
+            };
+            let condition = parser.or()?;
+            match parser.peek() {
+                None => Ok(condition),
+                Some(token) => Err(wrong_expression(expression, token)),
+            }
+        }
+        _ => {
+            let mut conditions = vec![];
+            for (key, value) in doc.hash()? {
+                match key.str()? {
+                    "min_visits" => {
+                        for (label, visits) in value.hash()? {
+                            conditions.push(Condition::Compare(
+                                Attribute::Visits(context.actor(label.str()?)?),
+                                Comparison::Ge,
+                                visits.number()?,
+                            ));
+                        }
+                    }
+                    "max_quality" => conditions.push(Condition::Compare(
+                        Attribute::Quality,
+                        Comparison::Le,
+                        value.number()?,
+                    )),
+                    key => {
+                        return Err(ParseError::WrongFormat(format!(
+                            "Unknown condition {}, expected min_visits or max_quality",
+                            key
+                        )))
+                    }
+                }
+            }
+            Ok(Condition::And(conditions))
+        }
+    }
+}
+
+fn wrong_expression(expression: &str, token: &str) -> ParseError {
+    ParseError::WrongFormat(format!("Unexpected {} in condition {}", token, expression))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' | ')' => tokens.push(c.to_string()),
+            '<' | '>' | '=' | '!' => {
+                let mut token = c.to_string();
+                if chars.peek() == Some(&'=') {
+                    token.push(chars.next().unwrap());
+                }
+                tokens.push(token);
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut token = c.to_string();
+                while let Some(next) = chars.peek() {
+                    if !(next.is_alphanumeric() || *next == '_' || *next == '.') {
+                        break;
+                    }
+                    token.push(chars.next().unwrap());
+                }
+                tokens.push(token);
+            }
+            c => return Err(wrong_expression(expression, &c.to_string())),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, `and` binding tighter than `or`.
+struct ExpressionParser<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    context: &'a ConditionContext<'a>,
+}
+
+impl ExpressionParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<String> {
+        let token =
+            self.tokens.get(self.position).cloned().ok_or_else(|| {
+                ParseError::WrongFormat(String::from("Unexpected end of condition"))
+            })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(ParseError::WrongFormat(format!(
+                "Expected {} instead of {} in condition",
+                expected, token
+            )));
+        }
+        Ok(())
+    }
+
+    /// Label between parentheses, such as the actor of `visits(recycling)`.
+    fn argument(&mut self) -> Result<String> {
+        self.expect("(")?;
+        let label = self.next()?;
+        self.expect(")")?;
+        Ok(label)
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut conditions = vec![self.and()?];
+        while self.peek() == Some("or") {
+            self.position += 1;
+            conditions.push(self.and()?);
+        }
+        Ok(match conditions.len() {
+            1 => conditions.pop().unwrap(),
+            _ => Condition::Or(conditions),
+        })
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut conditions = vec![self.factor()?];
+        while self.peek() == Some("and") {
+            self.position += 1;
+            conditions.push(self.factor()?);
+        }
+        Ok(match conditions.len() {
+            1 => conditions.pop().unwrap(),
+            _ => Condition::And(conditions),
+        })
+    }
+
+    fn factor(&mut self) -> Result<Condition> {
+        let token = self.next()?;
+        let attribute = match token.as_str() {
+            "not" => return Ok(Condition::Not(Box::new(self.factor()?))),
+            "(" => {
+                let condition = self.or()?;
+                self.expect(")")?;
+                return Ok(condition);
+            }
+            "contains" => {
+                let label = self.argument()?;
+                return Ok(Condition::Contains(self.context.component(&label)?));
+            }
+            "visits" => Attribute::Visits(self.context.actor(&self.argument()?)?),
+            "age" => Attribute::Age,
+            "quality" => Attribute::Quality,
+            "component" => Attribute::Component,
+            token => {
+                return Err(ParseError::WrongFormat(format!(
+                    "Unknown attribute {} in condition",
+                    token
+                )))
+            }
+        };
+        let comparison = match self.next()?.as_str() {
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            token => {
+                return Err(ParseError::WrongFormat(format!(
+                    "Unknown comparison {} in condition",
+                    token
+                )))
+            }
+        };
+        let value = self.next()?;
+        let value = match attribute {
+            Attribute::Component => self.context.component(&value)? as f64,
+            _ => value.parse::<f64>().map_err(|_| {
+                ParseError::WrongFormat(format!("Expected a number instead of {}", value))
+            })?,
+        };
+        // Ages are given in units of time
+        let value = match attribute {
+            Attribute::Age => value / self.context.dt,
+            _ => value,
+        };
+        Ok(Condition::Compare(attribute, comparison, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::engine::actor::SimpleSink;
+
+    use super::*;
+
+    const RECYCLING: u16 = 1000;
+
+    fn parse(expression: &str) -> Result<Condition> {
+        let actors: HashMap<String, AMActor> = HashMap::from([(
+            String::from("recycling"),
+            Arc::new(Mutex::new(SimpleSink::new(RECYCLING, 1))) as AMActor,
+        )]);
+        let components = HashMap::from([(String::from("plastic"), 1), (String::from("cap"), 2)]);
+        let context = ConditionContext {
+            actors: &actors,
+            components: &components,
+            dt: 0.5,
+        };
+        parse_condition(&Yaml::String(String::from(expression)), &context)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("quality < 0.5 or visits(recycling) >= 3 and not contains(cap)").unwrap(),
+            Condition::Or(vec![
+                Condition::Compare(Attribute::Quality, Comparison::Lt, 0.5),
+                Condition::And(vec![
+                    Condition::Compare(Attribute::Visits(RECYCLING), Comparison::Ge, 3.0),
+                    Condition::Not(Box::new(Condition::Contains(2))),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn parentheses_ages_and_components() {
+        assert_eq!(
+            parse("(age > 2 or component == plastic) and quality != 1").unwrap(),
+            Condition::And(vec![
+                Condition::Or(vec![
+                    // Ages are converted to timesteps
+                    Condition::Compare(Attribute::Age, Comparison::Gt, 4.0),
+                    Condition::Compare(Attribute::Component, Comparison::Eq, 1.0),
+                ]),
+                Condition::Compare(Attribute::Quality, Comparison::Ne, 1.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for expression in [
+            "visits(use) >= 3",
+            "contains(metal)",
+            "quality ~ 1",
+            "quality =< 1",
+            "size > 1",
+            "quality > high",
+            "(quality > 1",
+            "quality > 1 quality < 2",
+            "age >",
+        ] {
+            assert!(parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
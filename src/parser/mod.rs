@@ -1,3 +1,7 @@
 pub mod actors_parser;
+pub mod condition_parser;
+pub mod sensitivity_parser;
+pub mod sweep_parser;
 pub mod time_distribution_parser;
+pub mod validation;
 pub mod yaml_parser;
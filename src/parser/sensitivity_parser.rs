@@ -0,0 +1,92 @@
+use yaml_rust2::Yaml;
+
+use crate::analyzer::sensitivity::{Method, Output, Parameter, Sensitivity};
+
+use super::yaml_parser::{ParseError, Result, YamlParser};
+
+/// Parses a `sensitivity` section, such as
+/// ```yaml
+/// sensitivity:
+///   method: morris
+///   samples: 10
+///   levels: 4
+///   parameters:
+///     actors.use.clients.recycling.plastic: {min: 5, max: 15}
+///   outputs:
+///     landfill: {total: discard}
+///     in_use_peak: {peak_stock: use/plastic}
+/// ```
+pub fn parse_sensitivity(doc: &Yaml) -> Result<Sensitivity> {
+    let samples = doc.get("samples")?.int()?;
+    let method = match doc.get("method")?.str()? {
+        "morris" => {
+            let levels = match &doc["levels"] {
+                Yaml::BadValue => 4,
+                levels => levels.int()?,
+            };
+            if levels < 2 || levels % 2 != 0 {
+                return Err(ParseError::WrongFormat(String::from(
+                    "Morris levels must be an even number",
+                )));
+            }
+            Method::Morris { levels }
+        }
+        "sobol" => Method::Sobol,
+        method => {
+            return Err(ParseError::WrongFormat(format!(
+                "Unknown sensitivity method {}, expected morris or sobol",
+                method
+            )))
+        }
+    };
+    let mut parameters = vec![];
+    for (path, bounds) in doc.get("parameters")?.hash()? {
+        let (min, max) = (bounds.get("min")?, bounds.get("max")?);
+        parameters.push(Parameter {
+            path: String::from(path.str()?),
+            min: min.number()?,
+            max: max.number()?,
+            integer: min.as_i64().is_some() && max.as_i64().is_some(),
+        });
+    }
+    let mut outputs = vec![];
+    for (name, output) in doc.get("outputs")?.hash()? {
+        let (kind, label) = output
+            .hash()?
+            .front()
+            .ok_or_else(|| ParseError::SectionWrongType(String::from("outputs")))?;
+        let label = String::from(label.str()?);
+        let output = match kind.str()? {
+            "total" => Output::Total(label),
+            "peak_stock" => Output::PeakStock(label),
+            "final_stock" => Output::FinalStock(label),
+            kind => {
+                return Err(ParseError::WrongFormat(format!(
+                    "Unknown output {}, expected total, peak_stock or final_stock",
+                    kind
+                )))
+            }
+        };
+        outputs.push((String::from(name.str()?), output));
+    }
+    if parameters.is_empty() || outputs.is_empty() {
+        return Err(ParseError::WrongFormat(String::from(
+            "A sensitivity analysis needs parameters and outputs",
+        )));
+    }
+    Ok(Sensitivity {
+        method,
+        samples,
+        parameters,
+        outputs,
+    })
+}
+
+/// Yaml value of a sampled parameter.
+pub fn parameter_value(parameter: &Parameter, u: f64) -> Yaml {
+    let value = parameter.value(u);
+    match parameter.integer {
+        true => Yaml::Integer(value as i64),
+        false => Yaml::Real(format!("{:?}", value)),
+    }
+}
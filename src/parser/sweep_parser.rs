@@ -0,0 +1,210 @@
+use yaml_rust2::{Yaml, YamlEmitter};
+
+use super::yaml_parser::{ParseError, Result, YamlParser};
+
+/// Variants of a configuration, each one giving a value to every swept parameter.
+pub struct Sweep {
+    /// Dot separated paths of the parameters in the configuration, such as
+    /// `actors.use.clients.recycling.plastic`
+    pub parameters: Vec<String>,
+    pub variants: Vec<Vec<Yaml>>,
+}
+
+/// Parses a `sweep` section. Each parameter is given a list of values or a
+/// `{from, to, step}` range, and the variants are either the cartesian product of the
+/// values (`combine: product`, the default) or their i-th values (`combine: list`).
+pub fn parse_sweep(doc: &Yaml) -> Result<Sweep> {
+    let mut parameters = vec![];
+    let mut values = vec![];
+    for (path, value) in doc.get("parameters")?.hash()? {
+        parameters.push(String::from(path.str()?));
+        values.push(parse_values(value)?);
+    }
+    let combine = match &doc["combine"] {
+        Yaml::BadValue => "product",
+        combine => combine.str()?,
+    };
+    let variants = match combine {
+        "product" => values.iter().fold(vec![vec![]], |variants, values| {
+            variants
+                .iter()
+                .flat_map(|variant| {
+                    values.iter().map(move |value| {
+                        let mut variant: Vec<Yaml> = variant.clone();
+                        variant.push(value.clone());
+                        variant
+                    })
+                })
+                .collect()
+        }),
+        "list" => {
+            let n = values.first().map_or(0, |v| v.len());
+            if values.iter().any(|v| v.len() != n) {
+                return Err(ParseError::WrongFormat(String::from(
+                    "All the parameters of a list sweep must have the same number of values",
+                )));
+            }
+            (0..n)
+                .map(|i| values.iter().map(|v| v[i].clone()).collect())
+                .collect()
+        }
+        combine => {
+            return Err(ParseError::WrongFormat(format!(
+                "Unknown sweep combination {}, expected product or list",
+                combine
+            )))
+        }
+    };
+    Ok(Sweep {
+        parameters,
+        variants,
+    })
+}
+
+fn parse_values(doc: &Yaml) -> Result<Vec<Yaml>> {
+    match doc {
+        Yaml::Array(values) => Ok(values.clone()),
+        Yaml::Hash(_) => {
+            let (from, to, step) = (doc.get("from")?, doc.get("to")?, doc.get("step")?);
+            let (start, end, step_value) = (from.number()?, to.number()?, step.number()?);
+            if step_value <= 0.0 {
+                return Err(ParseError::WrongFormat(String::from(
+                    "The step of a sweep range must be positive",
+                )));
+            }
+            // Ranges of integers give integers, so they can replace integer parameters
+            let integers = [from, to, step].iter().all(|v| v.as_i64().is_some());
+            let n = ((end - start) / step_value + 1e-9).floor() as usize + 1;
+            Ok((0..n)
+                .map(|i| {
+                    let value = start + i as f64 * step_value;
+                    match integers {
+                        true => Yaml::Integer(value.round() as i64),
+                        false => Yaml::Real(format!("{:?}", value)),
+                    }
+                })
+                .collect())
+        }
+        value => Ok(vec![value.clone()]),
+    }
+}
+
+/// Replaces the parameter at the dot separated `path` of `doc` by `value`, the last
+/// key being added if missing. Integers replacing a float are converted, as float
+/// parameters do not accept integers.
+pub fn set_parameter(doc: &mut Yaml, path: &str, value: Yaml) -> Result<()> {
+    let mut current = doc;
+    let keys: Vec<&str> = path.split('.').collect();
+    for (depth, &key) in keys.iter().enumerate() {
+        if let Yaml::Hash(hash) = current {
+            let key = Yaml::String(String::from(key));
+            if depth == keys.len() - 1 && !hash.contains_key(&key) {
+                hash.insert(key.clone(), Yaml::Null);
+            }
+        }
+        current = match current {
+            Yaml::Hash(hash) => hash.get_mut(&Yaml::String(String::from(key))),
+            Yaml::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get_mut(i)),
+            _ => None,
+        }
+        .ok_or_else(|| ParseError::SectionMissing(String::from(path)))?;
+    }
+    *current = match (&current, value) {
+        (Yaml::Real(_), Yaml::Integer(value)) => Yaml::Real(format!("{:?}", value as f64)),
+        (_, value) => value,
+    };
+    Ok(())
+}
+
+/// Text of a swept value, as written in the CSV index of the runs.
+pub fn format_value(value: &Yaml) -> String {
+    match value {
+        Yaml::Real(value) | Yaml::String(value) => value.clone(),
+        Yaml::Integer(value) => value.to_string(),
+        Yaml::Boolean(value) => value.to_string(),
+        Yaml::Null => String::from("null"),
+        value => {
+            // Lists and hashes are written as quoted Yaml
+            let mut text = String::new();
+            YamlEmitter::new(&mut text).dump(value).unwrap();
+            let text = text.trim_start_matches("---").trim();
+            format!("\"{}\"", text.replace('"', "\"\""))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust2::YamlLoader;
+
+    use super::*;
+
+    fn load(doc: &str) -> Yaml {
+        YamlLoader::load_from_str(doc).unwrap().swap_remove(0)
+    }
+
+    fn formatted(variants: &[Vec<Yaml>]) -> Vec<Vec<String>> {
+        variants
+            .iter()
+            .map(|variant| variant.iter().map(format_value).collect())
+            .collect()
+    }
+
+    #[test]
+    fn product_combines_every_value() {
+        let sweep = parse_sweep(&load(
+            "parameters: {a: [1, 2], b: {from: 0.0, to: 1.0, step: 0.5}}",
+        ))
+        .unwrap();
+        assert_eq!(sweep.parameters, vec!["a", "b"]);
+        assert_eq!(
+            formatted(&sweep.variants),
+            vec![
+                vec!["1", "0.0"],
+                vec!["1", "0.5"],
+                vec!["1", "1.0"],
+                vec!["2", "0.0"],
+                vec!["2", "0.5"],
+                vec!["2", "1.0"],
+            ]
+        );
+    }
+
+    #[test]
+    fn list_pairs_the_values() {
+        let doc = "{combine: list, parameters: {a: [1, 2], b: [x, y]}}";
+        let sweep = parse_sweep(&load(doc)).unwrap();
+        assert_eq!(
+            formatted(&sweep.variants),
+            vec![vec!["1", "x"], vec!["2", "y"]]
+        );
+        let doc = "{combine: list, parameters: {a: [1, 2], b: [x]}}";
+        assert!(parse_sweep(&load(doc)).is_err());
+    }
+
+    #[test]
+    fn ranges_include_their_end() {
+        let values = parse_values(&load("{from: 0, to: 10, step: 5}")).unwrap();
+        assert_eq!(
+            values,
+            vec![Yaml::Integer(0), Yaml::Integer(5), Yaml::Integer(10)]
+        );
+        // 0.2 / 0.1 falls just below 2 in floating point
+        let values = parse_values(&load("{from: 0.1, to: 0.3, step: 0.1}")).unwrap();
+        assert_eq!(values.len(), 3);
+        assert!(parse_values(&load("{from: 0, to: 1, step: 0}")).is_err());
+    }
+
+    #[test]
+    fn parameters_are_set_by_path() {
+        let mut doc = load("{actors: {use: {rate: 0.5, list: [1, 2]}}}");
+        set_parameter(&mut doc, "actors.use.rate", Yaml::Integer(1)).unwrap();
+        set_parameter(&mut doc, "actors.use.list.1", Yaml::Integer(3)).unwrap();
+        set_parameter(&mut doc, "actors.use.delay", Yaml::Integer(2)).unwrap();
+        assert_eq!(
+            doc,
+            load("{actors: {use: {rate: 1.0, list: [1, 3], delay: 2}}}")
+        );
+        assert!(set_parameter(&mut doc, "actors.sort.rate", Yaml::Integer(1)).is_err());
+    }
+}
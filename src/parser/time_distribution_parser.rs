@@ -1,10 +1,10 @@
-use rand::thread_rng;
+use rand_chacha::ChaCha8Rng;
 use rand_distr::{Distribution, LogNormal};
 use yaml_rust2::Yaml;
 
-use crate::analyzer::Sampler;
+use crate::analyzer::{Cdf, Sampler};
 
-use super::yaml_parser::{Result, YamlParser};
+use super::yaml_parser::{ParseError, Result, YamlParser};
 use std::{
     collections::HashMap,
     sync::{Arc, LazyLock, Mutex},
@@ -21,25 +21,147 @@ pub fn add_time_callback_implementation(
     TIME_CALLBACK.lock().unwrap().insert(label, callback);
 }
 
+type CdfCallback = fn(&Yaml, f64) -> Result<Cdf>;
+pub static CDF_CALLBACK: LazyLock<Arc<Mutex<HashMap<String, CdfCallback>>>> =
+    LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Registers the cumulative distribution of the timesteps drawn by the time callback
+/// of the same `label`, used by the analyses computing expected curves.
+pub fn add_cdf_callback_implementation(label: String, callback: fn(&Yaml, f64) -> Result<Cdf>) {
+    CDF_CALLBACK.lock().unwrap().insert(label, callback);
+}
+
 pub fn import_default_time_callbacks() {
     add_time_callback_implementation(String::from("log_normal"), parse_lognormal);
     add_time_callback_implementation(String::from("constant"), constant);
+    add_cdf_callback_implementation(String::from("log_normal"), lognormal_cdf);
+    add_cdf_callback_implementation(String::from("constant"), constant_cdf);
+}
+
+/// Name and parameters of a distribution given as a single `name: parameters` pair.
+fn distribution(doc: &Yaml) -> Result<(&str, &Yaml)> {
+    let content = doc.hash()?;
+    if content.len() != 1 {
+        return Err(ParseError::WrongFormat(String::from(
+            "Exactly one distribution must be provided",
+        )));
+    }
+    let (name, parameters) = content.front().unwrap();
+    Ok((name.str()?, parameters))
+}
+
+/// Parses a distribution given as a single `name: parameters` pair, such as
+/// `log_normal: {mean: 8, std: 2}`.
+pub fn parse_time_distribution(doc: &Yaml, dt: f64) -> Result<Sampler> {
+    let (name, parameters) = distribution(doc)?;
+    let time_callbacks = TIME_CALLBACK.lock().unwrap();
+    let callback = time_callbacks
+        .get(name)
+        .ok_or_else(|| ParseError::UnknownTimeDistribution(String::from(name)))?;
+    callback(parameters, dt)
+}
+
+/// Parses the cumulative distribution of the timesteps drawn by the sampler of
+/// [parse_time_distribution] for the same document.
+pub fn parse_time_cdf(doc: &Yaml, dt: f64) -> Result<Cdf> {
+    let (name, parameters) = distribution(doc)?;
+    let cdf_callbacks = CDF_CALLBACK.lock().unwrap();
+    let callback = cdf_callbacks
+        .get(name)
+        .ok_or_else(|| ParseError::UnknownTimeDistribution(String::from(name)))?;
+    callback(parameters, dt)
 }
 
 fn parse_lognormal(doc: &Yaml, dt: f64) -> Result<Sampler> {
     let mean = doc.get("mean")?.float()?;
     let std = doc.get("std")?.float()?;
-    Ok(Box::new(move || {
-        let mut rng = thread_rng();
+    Ok(Arc::new(move |rng: &mut ChaCha8Rng| {
         (LogNormal::from_mean_cv(mean, std / mean)
             .unwrap()
-            .sample(&mut rng) as f32
+            .sample(rng) as f32
             / dt as f32)
             .round() as usize
-    }) as Box<dyn Fn() -> usize + Sync>)
+    }) as Sampler)
 }
 
 fn constant(doc: &Yaml, dt: f64) -> Result<Sampler> {
     let value = doc.get("value")?.float()?;
-    Ok(Box::new(move || (value / dt) as usize) as Box<dyn Fn() -> usize + Sync>)
+    Ok(Arc::new(move |_: &mut ChaCha8Rng| (value / dt) as usize) as Sampler)
+}
+
+/// The sampled time is rounded to the nearest timestep, so `k` timesteps or less are
+/// drawn for times below `(k + 0.5) * dt`.
+fn lognormal_cdf(doc: &Yaml, dt: f64) -> Result<Cdf> {
+    let mean = doc.get("mean")?.float()?;
+    let std = doc.get("std")?.float()?;
+    let sigma = (1.0 + (std / mean).powi(2)).ln().sqrt();
+    let mu = mean.ln() - sigma * sigma / 2.0;
+    Ok(Arc::new(move |timesteps: usize| {
+        let z = (((timesteps as f64 + 0.5) * dt).ln() - mu) / sigma;
+        0.5 * erfc(-z / std::f64::consts::SQRT_2)
+    }) as Cdf)
+}
+
+fn constant_cdf(doc: &Yaml, dt: f64) -> Result<Cdf> {
+    let value = doc.get("value")?.float()?;
+    let delay = (value / dt) as usize;
+    Ok(Arc::new(move |timesteps: usize| match timesteps >= delay {
+        true => 1.0,
+        false => 0.0,
+    }) as Cdf)
+}
+
+/// Complementary error function, with a relative error below 1.2e-7 (Numerical
+/// Recipes, 6.2).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = [
+        -1.26551223,
+        1.00002368,
+        0.37409196,
+        0.09678418,
+        -0.18628806,
+        0.27886807,
+        -1.13520398,
+        1.48851587,
+        -0.82215223,
+        0.17087277,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |acc, c| c + t * acc);
+    let res = t * (-z * z + polynomial).exp();
+    match x >= 0.0 {
+        true => res,
+        false => 2.0 - res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use yaml_rust2::YamlLoader;
+
+    use super::*;
+
+    #[test]
+    fn lognormal_cdf_matches_its_sampler() {
+        let doc = &YamlLoader::load_from_str("{mean: 8.0, std: 2.0}").unwrap()[0];
+        let sampler = parse_lognormal(doc, 0.5).unwrap();
+        let cdf = lognormal_cdf(doc, 0.5).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let draws = 100_000;
+        let below = (0..draws).filter(|_| sampler(&mut rng) <= 16).count();
+        assert!((below as f64 / draws as f64 - cdf(16)).abs() < 0.01);
+        assert!(cdf(0) < 1e-9);
+        assert!(cdf(100) > 1.0 - 1e-9);
+    }
+
+    #[test]
+    fn erfc_known_values() {
+        assert!((erfc(0.0) - 1.0).abs() < 1e-7);
+        assert!((erfc(1.0) - 0.157_299_207).abs() < 1e-7);
+        assert!((erfc(-1.0) - 1.842_700_793).abs() < 1e-7);
+    }
 }
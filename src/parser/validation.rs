@@ -0,0 +1,539 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use yaml_rust2::Yaml;
+
+use crate::engine::actor::{AMActor, RESIDUE};
+use crate::engine::time_series::TimeSeries;
+
+use super::condition_parser::{parse_condition, ConditionContext};
+use super::sweep_parser::format_value;
+use super::yaml_parser::{parse_time_series, ParseError, Result, YamlParser};
+
+/// Actor of the flow graph.
+pub struct Node {
+    pub label: String,
+    pub actor_type: String,
+    pub source: bool,
+    pub sink: bool,
+    /// Logged components, with the description of their residence time if given
+    pub logs: Vec<(String, Option<String>)>,
+}
+
+/// Route of a product from an actor to one of its clients.
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    /// Component label, or `residue`, `overflow` and `loss` for the special routes
+    pub product: String,
+    pub share: TimeSeries,
+    /// The route has a `when` condition
+    pub conditional: bool,
+}
+
+/// Actors and routes declared in the `actors` section, in the order of the file.
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// Builds the graph of the `actors` section and checks it can be simulated. Every
+/// problem found is reported at once in a [ParseError::InvalidGraph].
+pub fn validate_graph(
+    doc: &Yaml,
+    actors: &HashMap<String, AMActor>,
+    components: &HashMap<String, u16>,
+    init_sources: &[String],
+    dt: f64,
+) -> Result<Graph> {
+    let mut errors = vec![];
+    let graph = parse_graph(doc, actors, components, init_sources, dt, &mut errors)?;
+    check_references(doc, actors, components, dt, &mut errors)?;
+    graph.check(actors, components, &mut errors);
+    match errors.is_empty() {
+        true => Ok(graph),
+        false => Err(ParseError::InvalidGraph(errors)),
+    }
+}
+
+fn parse_graph(
+    doc: &Yaml,
+    actors: &HashMap<String, AMActor>,
+    components: &HashMap<String, u16>,
+    init_sources: &[String],
+    dt: f64,
+    errors: &mut Vec<ParseError>,
+) -> Result<Graph> {
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    for (label, content) in doc.hash()? {
+        let label = String::from(label.str()?);
+        let mut logs = vec![];
+        if let Yaml::Hash(log) = &content["log"] {
+            for (product_label, distribution) in log {
+                let description = match distribution {
+                    Yaml::Null => None,
+                    distribution => Some(describe(distribution)),
+                };
+                logs.push((String::from(product_label.str()?), description));
+            }
+        }
+        nodes.push(Node {
+            label: label.clone(),
+            actor_type: String::from(content.get("type")?.str()?),
+            source: init_sources.contains(&label),
+            sink: actors.get(&label).unwrap().lock().unwrap().is_sink(),
+            logs,
+        });
+        if let Yaml::Hash(compartments) = &content["compartments"] {
+            for (client_label, rate) in compartments {
+                let client_label = client_label.str()?;
+                if !actors.contains_key(client_label) {
+                    errors.push(ParseError::UnknownClient(
+                        label.clone(),
+                        String::from(client_label),
+                    ));
+                    continue;
+                }
+                let share = match parse_time_series(rate, dt) {
+                    Ok(share) => share,
+                    Err(error) => {
+                        errors.push(error);
+                        continue;
+                    }
+                };
+                edges.push(Edge {
+                    from: label.clone(),
+                    to: String::from(client_label),
+                    product: String::from("loss"),
+                    share,
+                    conditional: false,
+                });
+            }
+        }
+        let clients = &content["clients"];
+        if clients.is_badvalue() {
+            continue;
+        }
+        for (client_label, products) in clients.hash()? {
+            let client_label = client_label.str()?;
+            if !actors.contains_key(client_label) {
+                errors.push(ParseError::UnknownClient(
+                    label.clone(),
+                    String::from(client_label),
+                ));
+                continue;
+            }
+            for (product_label, value) in products.hash()? {
+                let product_label = product_label.str()?;
+                if product_label == "when" {
+                    continue;
+                }
+                if !components.contains_key(product_label)
+                    && product_label != "residue"
+                    && product_label != "overflow"
+                {
+                    errors.push(ParseError::UnknownProduct(
+                        label.clone(),
+                        String::from(product_label),
+                    ));
+                    continue;
+                }
+                // Malformed shares are reported with the other problems
+                let share = match parse_time_series(value, dt) {
+                    Ok(share) => share,
+                    Err(error) => {
+                        errors.push(error);
+                        continue;
+                    }
+                };
+                edges.push(Edge {
+                    from: label.clone(),
+                    to: String::from(client_label),
+                    product: String::from(product_label),
+                    share,
+                    conditional: !products["when"].is_badvalue(),
+                });
+            }
+        }
+    }
+    Ok(Graph { nodes, edges })
+}
+
+/// Short text of a Yaml value, such as `log_normal(mean: 8.0, std: 2.0)` for a time
+/// distribution.
+fn describe(doc: &Yaml) -> String {
+    match doc {
+        Yaml::Hash(hash) => {
+            let entries: Vec<String> = hash
+                .iter()
+                .map(|(key, value)| match value {
+                    Yaml::Hash(_) => format!("{}({})", format_value(key), describe(value)),
+                    value => format!("{}: {}", format_value(key), describe(value)),
+                })
+                .collect();
+            entries.join(", ")
+        }
+        Yaml::Array(values) => {
+            let values: Vec<String> = values.iter().map(describe).collect();
+            format!("[{}]", values.join(", "))
+        }
+        value => format_value(value),
+    }
+}
+
+/// Checks the labels of the logs and of the conditions refer to known actors and
+/// components.
+fn check_references(
+    doc: &Yaml,
+    actors: &HashMap<String, AMActor>,
+    components: &HashMap<String, u16>,
+    dt: f64,
+    errors: &mut Vec<ParseError>,
+) -> Result<()> {
+    let context = ConditionContext {
+        actors,
+        components,
+        dt,
+    };
+    for (label, content) in doc.hash()? {
+        let label = label.str()?;
+        if let Yaml::Hash(log) = &content["log"] {
+            for (product_label, _) in log {
+                let product_label = product_label.str()?;
+                if !components.contains_key(product_label) {
+                    errors.push(ParseError::UnknownProduct(
+                        String::from(label),
+                        String::from(product_label),
+                    ));
+                }
+            }
+        }
+        if let Yaml::Hash(clients) = &content["clients"] {
+            for (_, products) in clients {
+                if products["when"].is_badvalue() {
+                    continue;
+                }
+                if let Err(error) = parse_condition(&products["when"], &context) {
+                    errors.push(error);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Graph {
+    /// Routes from the actor `label` to its clients.
+    pub fn clients<'a>(&'a self, label: &'a str) -> impl Iterator<Item = &'a Edge> + 'a {
+        self.edges.iter().filter(move |edge| edge.from == label)
+    }
+
+    fn check(
+        &self,
+        actors: &HashMap<String, AMActor>,
+        components: &HashMap<String, u16>,
+        errors: &mut Vec<ParseError>,
+    ) {
+        let labels: HashMap<&u16, &String> = components
+            .iter()
+            .map(|(label, code)| (code, label))
+            .collect();
+        for node in self.nodes.iter() {
+            let has_clients = self.clients(&node.label).next().is_some();
+            if node.sink && has_clients {
+                errors.push(ParseError::SinkWithClients(node.label.clone()));
+            }
+            if !node.sink && !has_clients {
+                errors.push(ParseError::DeadEnd(node.label.clone()));
+                continue;
+            }
+            // Units of a component without route would stay in the actor
+            let mut outputs = actors[&node.label].lock().unwrap().outputs();
+            outputs.sort();
+            for code in outputs {
+                let label = match code {
+                    RESIDUE => "residue",
+                    code => labels[&code].as_str(),
+                };
+                if !self.clients(&node.label).any(|e| e.product == label) {
+                    errors.push(ParseError::MissingRoute(
+                        node.label.clone(),
+                        String::from(label),
+                    ));
+                }
+            }
+        }
+        for edge in self.edges.iter() {
+            let Some(code) = components.get(&edge.product) else {
+                continue;
+            };
+            if !actors[&edge.to].lock().unwrap().accepts(*code) {
+                errors.push(ParseError::NotAccepted(
+                    edge.from.clone(),
+                    edge.to.clone(),
+                    edge.product.clone(),
+                ));
+            }
+        }
+        let reached = self.reachable();
+        for node in self.nodes.iter() {
+            if !reached.contains(node.label.as_str()) {
+                errors.push(ParseError::Unreachable(node.label.clone()));
+            }
+        }
+        // Clients with a condition may only take the tokens matching it
+        for edge in self.edges.iter() {
+            if !edge.conditional && edge.share.is_zero() {
+                errors.push(ParseError::ZeroWeightRoute(
+                    edge.from.clone(),
+                    edge.to.clone(),
+                    edge.product.clone(),
+                ));
+            }
+        }
+        let produced: HashSet<u16> = actors
+            .values()
+            .flat_map(|actor| actor.lock().unwrap().products())
+            .collect();
+        let mut components: Vec<(&String, &u16)> = components.iter().collect();
+        components.sort_by_key(|(_, code)| **code);
+        for (label, code) in components {
+            if !produced.contains(code) {
+                errors.push(ParseError::NotProduced(label.clone()));
+            }
+        }
+    }
+
+    /// Labels of the actors units can reach from the sources.
+    fn reachable(&self) -> HashSet<&str> {
+        let mut reached: HashSet<&str> = HashSet::new();
+        let mut queue: VecDeque<&str> = self
+            .nodes
+            .iter()
+            .filter(|node| node.source)
+            .map(|node| node.label.as_str())
+            .collect();
+        while let Some(label) = queue.pop_front() {
+            if !reached.insert(label) {
+                continue;
+            }
+            queue.extend(self.clients(label).map(|edge| edge.to.as_str()));
+        }
+        reached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::yaml_parser::tests::config;
+
+    use super::*;
+
+    /// Errors found when validating the `actors` section `actors`.
+    fn errors(actors: &str) -> Vec<String> {
+        let doc = format!(
+            "
+global: {{time_window: 10, dt: 1.0}}
+components: [pellets, film]
+actors:
+{}",
+            actors
+        );
+        match config(&doc) {
+            Ok(_) => vec![],
+            Err(ParseError::InvalidGraph(errors)) => errors.iter().map(|e| e.to_string()).collect(),
+            Err(error) => vec![error.to_string()],
+        }
+    }
+
+    const PRODUCTION: &str = "
+  production:
+    type: SimpleSource
+    source: true
+    component: pellets
+    speed: {time: 1, quantity: 10}
+    clients: {extrusion: {pellets: 1}}
+  waste: {type: SimpleSink, component: film}";
+
+    /// Extrusion of the pellets of [PRODUCTION] into film for the waste.
+    const EXTRUSION: &str = "
+  extrusion:
+    type: TransformActor
+    component: film
+    input: pellets
+    ratio: {input: 10, output: 3}
+    clients: {waste: {film: 1}}";
+
+    /// Errors of the valid graph of [PRODUCTION] and [EXTRUSION], with `from` replaced
+    /// by `to` and the `extra` actors added.
+    fn errors_with(from: &str, to: &str, extra: &str) -> Vec<String> {
+        let actors = format!("{}{}", PRODUCTION, EXTRUSION).replace(from, to);
+        errors(&format!("{}{}", actors, extra))
+    }
+
+    #[test]
+    fn valid_graph_has_no_errors() {
+        assert!(errors_with("", "", "").is_empty());
+    }
+
+    #[test]
+    fn unknown_clients_are_reported() {
+        let landfill = "{waste: {film: 1}, landfill: {film: 1}}";
+        assert_eq!(
+            errors_with("{waste: {film: 1}}", landfill, ""),
+            vec!["Unknown client landfill of extrusion"]
+        );
+    }
+
+    #[test]
+    fn unknown_products_are_reported() {
+        assert_eq!(
+            errors_with("{waste: {film: 1}}", "{waste: {film: 1, bottles: 1}}", ""),
+            vec!["Unknown product bottles of extrusion"]
+        );
+    }
+
+    #[test]
+    fn unknown_compartments_are_reported() {
+        let littering = "
+  littering:
+    type: LossActor
+    component: film
+    compartments: {air: 0.1}
+    clients: {waste: {film: 1}}";
+        assert_eq!(
+            errors_with("{waste: {film: 1}}", "{littering: {film: 1}}", littering),
+            vec!["Unknown client air of littering"]
+        );
+    }
+
+    #[test]
+    fn unreachable_actors_are_reported() {
+        let landfill = "\n  landfill: {type: SimpleSink, component: film}";
+        assert_eq!(
+            errors_with("", "", landfill),
+            vec!["Actor landfill is unreachable from sources"]
+        );
+    }
+
+    #[test]
+    fn dead_ends_are_reported() {
+        assert_eq!(
+            errors_with("\n    clients: {waste: {film: 1}}", "", ""),
+            vec![
+                "Actor extrusion has no clients",
+                "Actor waste is unreachable from sources",
+            ]
+        );
+    }
+
+    #[test]
+    fn sinks_with_clients_are_reported() {
+        let landfill = "\n  landfill: {type: SimpleSink, component: film}";
+        let waste = "waste: {type: SimpleSink, component: film, clients: {landfill: {film: 1}}}";
+        assert_eq!(
+            errors_with(
+                "waste: {type: SimpleSink, component: film}",
+                waste,
+                landfill
+            ),
+            vec!["Sink waste cannot have clients"]
+        );
+    }
+
+    #[test]
+    fn zero_weight_routes_are_reported() {
+        let landfill = "\n  landfill: {type: SimpleSink, component: film}";
+        assert_eq!(
+            errors_with(
+                "{waste: {film: 1}}",
+                "{waste: {film: 1}, landfill: {film: 0}}",
+                landfill
+            ),
+            vec!["Route of film from extrusion to landfill has a zero weight"]
+        );
+    }
+
+    #[test]
+    fn every_malformed_share_is_reported() {
+        let actors = format!("{}{}", PRODUCTION, EXTRUSION)
+            .replace("{pellets: 1}", "{pellets: many}")
+            .replace("{film: 1}", "{film: {exponential: {0: 1}}}");
+        // The routes left out also leave the actors without clients
+        let errors = errors(&actors);
+        assert!(errors[0].contains("Time series must be a number"));
+        assert!(errors[1].contains("Unknown time series exponential"));
+    }
+
+    #[test]
+    fn transform_losses_need_a_residue_route() {
+        let extrusion = "
+  extrusion:
+    type: TransformActor
+    component: film
+    input: pellets
+    ratio: {input: 10, output: 3}
+    loss: 2
+    clients: {waste: {film: 1}}";
+        assert_eq!(
+            errors(&format!("{}{}", PRODUCTION, extrusion)),
+            vec!["Actor extrusion has no route for residue"]
+        );
+        let routed = format!("{}\n      residues: {{residue: 1}}", extrusion).replace(
+            "clients: {waste: {film: 1}}",
+            "clients:\n      waste: {film: 1}",
+        );
+        let residues = "\n  residues: {type: SimpleSink, component: pellets}";
+        assert!(errors(&format!("{}{}{}", PRODUCTION, routed, residues)).is_empty());
+    }
+
+    #[test]
+    fn partial_recoveries_need_a_residue_route() {
+        let actors = "
+  production:
+    type: SimpleSource
+    source: true
+    component: film
+    speed: {time: 1, quantity: 10}
+    clients: {dismantling: {film: 1}}
+  dismantling:
+    type: DisassemblyActor
+    component: film
+    recovery: {pellets: 0.5}
+    clients:
+      recycling: {pellets: 1}
+  recycling: {type: SimpleSink, component: pellets}";
+        assert_eq!(
+            errors(actors),
+            vec!["Actor dismantling has no route for residue"]
+        );
+        let routed = actors.replace(
+            "recycling: {pellets: 1}",
+            "recycling: {pellets: 1, residue: 1}",
+        );
+        assert!(errors(&routed).is_empty());
+    }
+
+    #[test]
+    fn simple_actors_only_accept_their_components() {
+        let actors = "
+  production:
+    type: SimpleSource
+    source: true
+    component: pellets
+    speed: {time: 1, quantity: 10}
+    clients: {use: {pellets: 1}}
+  use:
+    type: SimpleActor
+    component: film
+    clients: {waste: {film: 1}}
+  waste: {type: SimpleSink, component: film}";
+        assert_eq!(
+            errors(actors),
+            vec![
+                "Route of pellets from production to use, which does not accept it",
+                "No actor produces component film",
+            ]
+        );
+    }
+}
@@ -2,15 +2,19 @@ use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 
-use threadpool::ThreadPool;
 use yaml_rust2::yaml::Hash;
 use yaml_rust2::{Yaml, YamlLoader};
 
-use crate::analyzer::Sampler;
-use crate::engine::actor::AMActor;
+use crate::analyzer::{Cdf, Sampler};
+use crate::engine::actor::{AMActor, Routing, LOSS, OVERFLOW, RESIDUE};
+use crate::engine::route::Route;
+use crate::engine::scheduler::AMScheduler;
+use crate::engine::time_series::TimeSeries;
 use crate::parser::actors_parser::ACTORS;
 
-use super::time_distribution_parser::TIME_CALLBACK;
+use super::condition_parser::{parse_condition, ConditionContext};
+use super::time_distribution_parser::{parse_time_cdf, parse_time_distribution};
+use super::validation::{validate_graph, Graph};
 pub type Result<T> = std::result::Result<T, ParseError>;
 
 #[derive(Debug, Clone)]
@@ -22,6 +26,31 @@ pub enum ParseError {
     UnknownActor(String),
     UnknownTimeDistribution(String),
     WrongFormat(String),
+    /// Client of an actor that is not an actor
+    UnknownClient(String, String),
+    /// Product routed or logged by an actor that is not a component
+    UnknownProduct(String, String),
+    /// Actor no units can reach from the sources
+    Unreachable(String),
+    /// Actor other than a sink without clients
+    DeadEnd(String),
+    SinkWithClients(String),
+    /// Component an actor sends to its clients without any route for it
+    MissingRoute(String, String),
+    /// Route of a product from an actor to a client that does not accept it
+    NotAccepted(String, String, String),
+    /// Route of a product from an actor to a client whose weight is never positive
+    ZeroWeightRoute(String, String, String),
+    /// Component no actor creates
+    NotProduced(String),
+    /// All the problems found when validating the graph of actors
+    InvalidGraph(Vec<ParseError>),
+    /// Error of an actor that stopped the simulation
+    Simulation(String),
+    /// Breakdown of each component whose mass balance does not close
+    Balance(Vec<String>),
+    /// Component named after a key of the `clients` section
+    ReservedName(String),
 }
 
 impl fmt::Display for ParseError {
@@ -34,6 +63,34 @@ impl fmt::Display for ParseError {
             ParseError::UnknownActor(s) => write!(f, "Unknown actor {}", s),
             ParseError::UnknownTimeDistribution(s) => write!(f, "Unknown time distribution {}", s),
             ParseError::WrongFormat(s) => write!(f, "The config file is not well formatted: {}", s),
+            ParseError::UnknownClient(a, c) => write!(f, "Unknown client {} of {}", c, a),
+            ParseError::UnknownProduct(a, p) => write!(f, "Unknown product {} of {}", p, a),
+            ParseError::Unreachable(a) => write!(f, "Actor {} is unreachable from sources", a),
+            ParseError::DeadEnd(a) => write!(f, "Actor {} has no clients", a),
+            ParseError::SinkWithClients(a) => write!(f, "Sink {} cannot have clients", a),
+            ParseError::MissingRoute(a, p) => write!(f, "Actor {} has no route for {}", a, p),
+            ParseError::NotAccepted(a, c, p) => {
+                write!(
+                    f,
+                    "Route of {} from {} to {}, which does not accept it",
+                    p, a, c
+                )
+            }
+            ParseError::ZeroWeightRoute(a, c, p) => {
+                write!(f, "Route of {} from {} to {} has a zero weight", p, a, c)
+            }
+            ParseError::NotProduced(c) => write!(f, "No actor produces component {}", c),
+            ParseError::InvalidGraph(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "Invalid graph:\n{}", errors.join("\n"))
+            }
+            ParseError::Simulation(s) => write!(f, "Simulation failed: {}", s),
+            ParseError::Balance(failures) => {
+                write!(f, "Mass balance does not close for\n{}", failures.concat())
+            }
+            ParseError::ReservedName(c) => {
+                write!(f, "Component {} has a name reserved for routes", c)
+            }
         }
     }
 }
@@ -46,7 +103,15 @@ impl std::convert::From<std::io::Error> for ParseError {
 
 pub struct GlobalConfig {
     pub time_window: usize,
+    /// Seed of the random streams, a random one is used if missing
+    pub seed: Option<u64>,
     pub dt: f64,
+    /// Number of independent runs of the simulation
+    pub replications: usize,
+    /// Percentiles reported over the replications
+    pub percentiles: Vec<f64>,
+    /// Duration of the bins of the flows between actors
+    pub flow_bin: f64,
 }
 
 pub trait YamlParser {
@@ -54,6 +119,8 @@ pub trait YamlParser {
     fn str(&self) -> Result<&str>;
     fn int(&self) -> Result<usize>;
     fn float(&self) -> Result<f64>;
+    /// Reads either an integer or a float
+    fn number(&self) -> Result<f64>;
     fn bool(&self) -> Result<bool>;
     fn hash(&self) -> Result<&Hash>;
 }
@@ -100,6 +167,13 @@ impl YamlParser for Yaml {
             Some(data) => Ok(data),
         }
     }
+
+    fn number(&self) -> Result<f64> {
+        match self.as_i64() {
+            None => self.float(),
+            Some(data) => Ok(data as f64),
+        }
+    }
 }
 
 fn parse_global(doc: &Yaml) -> Result<GlobalConfig> {
@@ -108,47 +182,194 @@ fn parse_global(doc: &Yaml) -> Result<GlobalConfig> {
     }
     let time_window = doc.get("time_window")?.int()?;
     let dt = doc.get("dt")?.float()?;
-    Ok(GlobalConfig { time_window, dt })
+    let seed = match &doc["seed"] {
+        Yaml::BadValue => None,
+        seed => Some(seed.int()? as u64),
+    };
+    let replications = match &doc["replications"] {
+        Yaml::BadValue => 1,
+        replications => replications.int()?.max(1),
+    };
+    let percentiles = match &doc["percentiles"] {
+        Yaml::BadValue => vec![5.0, 50.0, 95.0],
+        Yaml::Array(percentiles) => percentiles
+            .iter()
+            .map(|p| p.number())
+            .collect::<Result<_>>()?,
+        _ => return Err(ParseError::SectionWrongType(String::from("percentiles"))),
+    };
+    let flow_bin = match &doc["flow_bin"] {
+        Yaml::BadValue => 1.0,
+        flow_bin => flow_bin.number()?,
+    };
+    Ok(GlobalConfig {
+        time_window,
+        seed,
+        dt,
+        replications,
+        percentiles,
+        flow_bin,
+    })
 }
 
+/// Condition and special routes of a client, which cannot be component names.
+const RESERVED_PRODUCTS: [&str; 4] = ["when", "residue", "overflow", "loss"];
+
 fn parse_components(doc: &Yaml) -> Result<HashMap<String, u16>> {
     let mut components = HashMap::new();
-    let mut id = 1u16;
-    for label in doc.clone() {
+    for (id, label) in (1u16..).zip(doc.clone()) {
         let _ = match label.as_str() {
             None => return Err(ParseError::SectionWrongType(String::from("components"))),
+            Some(l) if RESERVED_PRODUCTS.contains(&l) => {
+                return Err(ParseError::ReservedName(String::from(l)))
+            }
             Some(l) => components.insert(String::from(l), id),
         };
-        id += 1;
     }
     Ok(components)
 }
 
+/// Reads a CSV file with a header and `time,value` rows.
+pub fn read_time_series_csv(path: &str) -> Result<Vec<(f64, f64)>> {
+    let content = fs::read_to_string(path)?;
+    let mut points = vec![];
+    for line in content.lines().skip(1).filter(|l| !l.trim().is_empty()) {
+        let values: Vec<f64> = line
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| ParseError::WrongFormat(format!("Wrong row {} in {}", line, path)))?;
+        if values.len() != 2 {
+            return Err(ParseError::WrongFormat(format!(
+                "Rows of {} must be time,value",
+                path
+            )));
+        }
+        points.push((values[0], values[1]));
+    }
+    Ok(points)
+}
+
+/// Parses `points` given either inline as `time: value` pairs or as the path of a CSV
+/// file read by [read_time_series_csv]. Times are converted into timesteps.
+fn parse_time_series_points(doc: &Yaml, dt: f64) -> Result<Vec<(usize, f64)>> {
+    let mut points = match doc {
+        Yaml::String(path) => read_time_series_csv(path)?,
+        _ => doc
+            .hash()?
+            .iter()
+            .map(|(time, value)| Ok((time.number()?, value.number()?)))
+            .collect::<Result<Vec<(f64, f64)>>>()?,
+    };
+    if points.is_empty() {
+        return Err(ParseError::WrongFormat(String::from(
+            "Time series need at least one point",
+        )));
+    }
+    points.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    Ok(points
+        .into_iter()
+        .map(|(time, value)| ((time / dt).round() as usize, value))
+        .collect())
+}
+
+/// Parses a value varying over time: either a constant or a `linear` or `step` schedule.
+pub fn parse_time_series(doc: &Yaml, dt: f64) -> Result<TimeSeries> {
+    match doc {
+        Yaml::Integer(_) | Yaml::Real(_) => Ok(TimeSeries::Constant(doc.number()?)),
+        Yaml::Hash(content) if content.len() == 1 => {
+            let (kind, points) = content.front().unwrap();
+            match kind.str()? {
+                "linear" => Ok(TimeSeries::Linear(parse_time_series_points(points, dt)?)),
+                "step" => Ok(TimeSeries::Step(parse_time_series_points(points, dt)?)),
+                kind => Err(ParseError::WrongFormat(format!(
+                    "Unknown time series {}, expected linear or step",
+                    kind
+                ))),
+            }
+        }
+        _ => Err(ParseError::WrongFormat(String::from(
+            "Time series must be a number or a linear or step schedule",
+        ))),
+    }
+}
+
 fn parse_clients(
     doc: &Yaml,
     actors: &mut HashMap<String, AMActor>,
     components: &HashMap<String, u16>,
+    dt: f64,
 ) -> Result<()> {
     let actors_doc = doc.hash()?;
     for (actor_label, content) in actors_doc {
+        let actor_label = actor_label.str()?.to_string();
+        let actor = actors.get(&actor_label).unwrap();
+        // Compartments receive the lost units, their rate being the weight of the route
+        if let Yaml::Hash(compartments) = &content["compartments"] {
+            for (client_label, rate) in compartments {
+                let client_label = client_label.str()?;
+                let client = actors.get(client_label).ok_or_else(|| {
+                    ParseError::UnknownClient(actor_label.clone(), String::from(client_label))
+                })?;
+                let client_code = client.lock().unwrap().code();
+                actor.lock().unwrap().register(
+                    client_code,
+                    LOSS,
+                    Route::new(parse_time_series(rate, dt)?),
+                    client.clone(),
+                );
+            }
+        }
         let clients = &content["clients"];
         if clients.is_badvalue() {
             continue;
         }
-        let actor_label = actor_label.str()?.to_string();
-        let actor = actors.get(&actor_label).unwrap();
+        let context = ConditionContext {
+            actors,
+            components,
+            dt,
+        };
         for (client_label, products) in clients.hash()? {
-            let client = actors.get(&client_label.str()?.to_string()).unwrap();
+            let client_label = client_label.str()?;
+            let client = actors.get(client_label).ok_or_else(|| {
+                ParseError::UnknownClient(actor_label.clone(), String::from(client_label))
+            })?;
             let client_code = client.lock().unwrap().code();
+            let condition = match &products["when"] {
+                Yaml::BadValue => None,
+                when => Some(parse_condition(when, &context)?),
+            };
             for (product_label, value) in products.hash()? {
+                let product_label = product_label.str()?;
+                if product_label == "when" {
+                    continue;
+                }
+                let code_product = match components.get(product_label) {
+                    Some(code) => *code,
+                    None if product_label == "residue" => RESIDUE,
+                    None if product_label == "overflow" => OVERFLOW,
+                    None => {
+                        return Err(ParseError::UnknownProduct(
+                            actor_label.clone(),
+                            String::from(product_label),
+                        ))
+                    }
+                };
                 actor.lock().unwrap().register(
                     client_code,
-                    *components.get(&product_label.str()?.to_string()).unwrap(),
-                    value.int()? as u32,
+                    code_product,
+                    Route {
+                        share: parse_time_series(value, dt)?,
+                        condition: condition.clone(),
+                    },
                     client.clone(),
                 );
             }
         }
+        // Applied after the registrations, as broadcasts are created when registering
+        if let Some(routing) = content["routing"].as_str() {
+            actor.lock().unwrap().set_routing(Routing::parse(routing)?);
+        }
     }
     Ok(())
 }
@@ -156,7 +377,7 @@ fn parse_clients(
 fn parse_actors(
     doc: &Yaml,
     components: &HashMap<String, u16>,
-    threadpool: ThreadPool,
+    scheduler: AMScheduler,
 ) -> Result<HashMap<String, AMActor>> {
     let actors = doc.hash()?;
     let mut res: HashMap<String, AMActor> = HashMap::new();
@@ -171,7 +392,7 @@ fn parse_actors(
             .ok_or(ParseError::UnknownActor(actor_type))?;
         res.insert(
             label,
-            actor_callback(content, index, components.clone(), threadpool.clone())?,
+            actor_callback(content, index, components.clone(), scheduler.clone())?,
         );
         index += index_step;
     }
@@ -181,7 +402,10 @@ fn parse_actors(
 pub struct ActorLogInfos {
     pub index: usize,
     pub product_code: String,
+    pub component: u16,
     pub time_sampler: Option<Sampler>,
+    /// Cumulative distribution of the sampler, when known for its distribution
+    pub time_cdf: Option<Cdf>,
 }
 
 fn parse_logs(
@@ -201,37 +425,40 @@ fn parse_logs(
         }
         for (product_label, content) in log.hash()? {
             let product_label = product_label.str()?.to_string();
-            let code = *components.get(&product_label).unwrap() + actor.lock().unwrap().code();
+            let component = *components.get(&product_label).ok_or_else(|| {
+                ParseError::UnknownProduct(actor_label.clone(), product_label.clone())
+            })?;
+            let code = component + actor.lock().unwrap().code();
 
             if content.is_null() {
                 res.insert(
                     code,
                     ActorLogInfos {
                         product_code: format!("{actor_label}/{product_label}"),
+                        component,
                         index: res.len(),
                         time_sampler: None,
+                        time_cdf: None,
                     },
                 );
                 continue;
             }
-            let content = content.hash()?;
-            if content.len() > 1 {
-                return Err(ParseError::WrongFormat(format!(
-                    "Multiple distributions were provided in actor {}",
-                    actor_label
-                )));
-            }
-            let callback_doc = content.keys().next().unwrap();
-            let callback_name = callback_doc.str()?.to_string();
-            let time_callbacks = TIME_CALLBACK.lock().unwrap();
-            let time_creation_callback = time_callbacks.get(&callback_name).unwrap();
-            let time_callback = time_creation_callback(content.get(callback_doc).unwrap(), dt)?;
+            let time_callback = parse_time_distribution(content, dt)?;
+            let time_cdf = parse_time_cdf(content, dt).ok();
+            // The logged distribution is the time the actor holds the product
+            actor.lock().unwrap().set_residence_time(
+                component,
+                time_callback.clone(),
+                time_cdf.clone(),
+            );
             res.insert(
                 code,
                 ActorLogInfos {
                     product_code: format!("{actor_label}/{product_label}"),
+                    component,
                     index: res.len(),
                     time_sampler: Some(time_callback),
+                    time_cdf,
                 },
             );
         }
@@ -245,7 +472,9 @@ fn parse_init_sources(doc: &Yaml) -> Result<Vec<String>> {
     for (actor_label, content) in actors_doc {
         let actor_label = actor_label.str()?.to_string();
         let init_doc = &content["source"];
-        if init_doc.is_badvalue() || !init_doc.bool()? {
+        // Actors storing material at the start supply it as sources
+        let initial_stock = !content["initial_stock"].is_badvalue();
+        if !initial_stock && (init_doc.is_badvalue() || !init_doc.bool()?) {
             continue;
         }
         res.push(actor_label);
@@ -259,28 +488,181 @@ pub struct Config {
     pub components: HashMap<String, u16>,
     pub logs: HashMap<u16, ActorLogInfos>,
     pub init_sources: Vec<String>,
-    pub pool: ThreadPool,
+    pub scheduler: AMScheduler,
+    pub graph: Graph,
 }
 
-pub fn parse_config(path: String, pool: ThreadPool) -> Result<Config> {
-    let config: String = fs::read_to_string(path)?;
-    let docs = YamlLoader::load_from_str(&config).unwrap();
-    let doc = &docs[0];
+/// Reads the Yaml document of the file at `path`.
+pub fn load_yaml(path: &str) -> Result<Yaml> {
+    let content: String = fs::read_to_string(path)?;
+    let mut docs = YamlLoader::load_from_str(&content)
+        .map_err(|e| ParseError::WrongFormat(format!("{} in {}", e, path)))?;
+    if docs.is_empty() {
+        return Err(ParseError::WrongFormat(format!("{} is empty", path)));
+    }
+    Ok(docs.swap_remove(0))
+}
+
+pub fn parse_config(path: String, scheduler: AMScheduler) -> Result<Config> {
+    parse_config_doc(&load_yaml(&path)?, scheduler)
+}
+
+pub fn parse_config_doc(doc: &Yaml, scheduler: AMScheduler) -> Result<Config> {
     let global_doc = doc.get("global")?;
     let global = parse_global(global_doc)?;
+    if let Some(seed) = global.seed {
+        scheduler.lock().unwrap().seed = seed;
+    }
+    scheduler.lock().unwrap().dt = global.dt;
     let components = doc.get("components")?;
     let components = parse_components(components)?;
     let actors_doc = doc.get("actors")?;
-    let mut actors = parse_actors(actors_doc, &components, pool.clone())?;
-    parse_clients(actors_doc, &mut actors, &components)?;
-    let logs = parse_logs(actors_doc, &components, &actors, global.dt)?;
+    let mut actors = parse_actors(actors_doc, &components, scheduler.clone())?;
     let init_sources = parse_init_sources(actors_doc)?;
+    let graph = validate_graph(actors_doc, &actors, &components, &init_sources, global.dt)?;
+    parse_clients(actors_doc, &mut actors, &components, global.dt)?;
+    let logs = parse_logs(actors_doc, &components, &actors, global.dt)?;
     Ok(Config {
         global,
         actors,
         components,
         logs,
         init_sources,
-        pool,
+        scheduler,
+        graph,
     })
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::engine::scheduler::{run, Scheduler, SimulationResult};
+    use crate::parser::actors_parser::import_default_actors;
+    use crate::parser::time_distribution_parser::import_default_time_callbacks;
+
+    use super::*;
+
+    /// Configuration of the Yaml document `doc`, with the default actors and time
+    /// distributions.
+    pub fn config(doc: &str) -> Result<Config> {
+        import_default_actors();
+        import_default_time_callbacks();
+        let doc = YamlLoader::load_from_str(doc).unwrap().swap_remove(0);
+        parse_config_doc(&doc, Scheduler::new())
+    }
+
+    /// Runs the simulation of `config` over its time window.
+    pub fn simulate(config: &Config) -> SimulationResult<()> {
+        let sources: Vec<AMActor> = config
+            .init_sources
+            .iter()
+            .map(|label| config.actors[label].clone())
+            .collect();
+        let max_time = (config.global.time_window as f64 / config.global.dt) as usize;
+        run(&config.scheduler, &sources, max_time)
+    }
+
+    /// Stochastic routes and residence times, the seed replacing `SEED`.
+    const RANDOM: &str = "
+global: {time_window: 30, dt: 1.0, seed: SEED}
+components: [pellets]
+actors:
+  production:
+    type: SimpleSource
+    source: true
+    component: pellets
+    speed: {time: 1, quantity: 100}
+    max_production: 1000
+    clients: {use: {pellets: 1}}
+  use:
+    type: SimpleActor
+    component: pellets
+    log: {pellets: {log_normal: {mean: 5.0, std: 2.0}}}
+    routing: stochastic
+    clients: {recycling: {pellets: 1}, waste: {pellets: 3}}
+  recycling: {type: SimpleSink, component: pellets}
+  waste: {type: SimpleSink, component: pellets}";
+
+    /// Units sent per timestep along each route when simulating [RANDOM] with `seed`.
+    fn flows(seed: u64) -> BTreeMap<(u16, u16, u16), BTreeMap<usize, u64>> {
+        let config = config(&RANDOM.replace("SEED", &seed.to_string())).unwrap();
+        simulate(&config).unwrap();
+        let flows = config.scheduler.lock().unwrap().flows.clone();
+        flows
+    }
+
+    #[test]
+    fn runs_with_the_same_seed_are_identical() {
+        assert_eq!(flows(7), flows(7));
+        assert_ne!(flows(7), flows(8));
+    }
+
+    #[test]
+    fn recycled_units_count_their_visits_and_lose_quality() {
+        let config = config(
+            "
+global: {time_window: 10, dt: 1.0}
+components: [pellets]
+actors:
+  production:
+    type: SimpleSource
+    source: true
+    component: pellets
+    speed: {time: 1, quantity: 100}
+    max_production: 500
+    clients: {use: {pellets: 1}}
+  use:
+    type: SimpleActor
+    component: pellets
+    clients:
+      recycling: {pellets: 1}
+      incineration: {pellets: 1, when: {min_visits: {recycling: 2}}}
+  recycling:
+    type: SimpleActor
+    component: pellets
+    degradation: 0.2
+    clients: {use: {pellets: 1}}
+  incineration: {type: SimpleSink, component: pellets}",
+        )
+        .unwrap();
+        simulate(&config).unwrap();
+        let recycling = config.actors["recycling"].lock().unwrap().code();
+        let tokens = config.actors["incineration"].lock().unwrap().tokens();
+        assert_eq!(tokens.iter().map(|t| t.count).sum::<u64>(), 500);
+        for token in tokens {
+            assert_eq!(token.visits(recycling), 2);
+            assert!((token.quality - 0.64).abs() < 1e-9, "{}", token.quality);
+        }
+    }
+
+    const ROUTED: &str = "
+global: {time_window: 10, dt: 1.0}
+components: [pellets]
+actors:
+  production:
+    type: SimpleSource
+    source: true
+    component: pellets
+    speed: {time: 1, quantity: 10}
+    routing: ROUTING
+    clients: {waste: {pellets: 1}}
+  waste: {type: SimpleSink, component: pellets}";
+
+    #[test]
+    fn routing_is_an_option_of_the_actor() {
+        assert!(config(&ROUTED.replace("ROUTING", "stochastic")).is_ok());
+        assert!(matches!(
+            config(&ROUTED.replace("ROUTING", "bogus")),
+            Err(ParseError::WrongFormat(_))
+        ));
+    }
+
+    #[test]
+    fn components_cannot_be_named_after_route_keys() {
+        let doc = ROUTED
+            .replace("ROUTING", "stochastic")
+            .replace("[pellets]", "[pellets, when]");
+        assert!(matches!(config(&doc), Err(ParseError::ReservedName(c)) if c == "when"));
+    }
+}